@@ -0,0 +1,8 @@
+use std::process::ExitCode;
+
+use clap::Parser;
+use icefunprog::cli::{self, Cli};
+
+fn main() -> ExitCode {
+    cli::exit(Cli::parse().run())
+}
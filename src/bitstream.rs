@@ -0,0 +1,309 @@
+use std::fmt::Display;
+use std::io::{BufReader, ErrorKind, Read};
+
+use tracing::{debug, instrument};
+
+use crate::err::Error;
+
+const PREAMBLE: u32 = 0x7eaa_997e;
+const COMMENT_START: u32 = 0xff00;
+
+/// iCE40 die, identified from the CRAM bank dimensions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Part {
+    /// iCE40 LP/HX1K
+    Hx1k,
+    /// iCE40 LP/HX4K and LP/HX8K
+    Hx8k,
+    /// iCE40 UP5K
+    Up5k,
+    Unknown {
+        width: usize,
+        height: usize,
+    },
+}
+
+impl Part {
+    fn from_bank(width: usize, height: usize) -> Self {
+        match (width, height) {
+            (332, 144) => Self::Hx1k,
+            (872, 272) => Self::Hx8k,
+            (692, 336) => Self::Up5k,
+            _ => Self::Unknown { width, height },
+        }
+    }
+}
+
+impl Display for Part {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Hx1k => write!(f, "iCE40 HX1K"),
+            Self::Hx8k => write!(f, "iCE40 HX8K"),
+            Self::Up5k => write!(f, "iCE40 UP5K"),
+            Self::Unknown { width, height } => write!(f, "unknown {width}x{height} bank"),
+        }
+    }
+}
+
+/// One block of CRAM or BRAM data written to a bank.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub bank: usize,
+    /// Width in bits
+    pub width: usize,
+    /// Height in rows
+    pub height: usize,
+    pub offset: usize,
+}
+
+impl Frame {
+    /// Size of the frame data in bytes
+    #[must_use]
+    pub fn data_len(&self) -> usize {
+        (self.width * self.height).div_ceil(8)
+    }
+}
+
+/// Summary of a parsed iCE40 bitstream.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bitstream {
+    pub comments: Vec<String>,
+    pub cram: Vec<Frame>,
+    pub bram: Vec<Frame>,
+    /// Number of CRC checks which passed
+    pub crc_checks: usize,
+    /// True if the stream ends with a warmboot reboot rather than a wakeup
+    pub reboot: bool,
+    /// Length in bytes, up to the end of the wakeup or reboot command
+    pub len: usize,
+}
+
+impl Bitstream {
+    /// Parse and validate a bitstream.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the bitstream is malformed, truncated or fails a CRC check.
+    #[instrument(skip_all)]
+    pub fn parse(reader: impl Read) -> Result<Self, Error> {
+        Parser {
+            reader: BufReader::new(reader),
+            crc: 0xffff,
+            offset: 0,
+        }
+        .parse()
+    }
+
+    /// The die this bitstream was built for.
+    #[must_use]
+    pub fn part(&self) -> Option<Part> {
+        self.cram
+            .first()
+            .map(|frame| Part::from_bank(frame.width, frame.height))
+    }
+}
+
+struct Parser<R: Read> {
+    reader: R,
+    crc: u16,
+    offset: usize,
+}
+
+fn bitstream_err(msg: impl Into<String>) -> Error {
+    Error::Bitstream(msg.into())
+}
+
+fn crc16(crc: u16, byte: u8) -> u16 {
+    let mut crc = crc ^ (u16::from(byte) << 8);
+    for _ in 0..8 {
+        crc = if crc & 0x8000 == 0 {
+            crc << 1
+        } else {
+            (crc << 1) ^ 0x1021
+        };
+    }
+    crc
+}
+
+impl<R: Read> Parser<R> {
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        let mut buf = [0u8];
+        self.reader.read_exact(&mut buf).map_err(|err| {
+            if err.kind() == ErrorKind::UnexpectedEof {
+                bitstream_err(format!("Truncated at {:#x}", self.offset))
+            } else {
+                err.into()
+            }
+        })?;
+        self.offset += 1;
+        self.crc = crc16(self.crc, buf[0]);
+        Ok(buf[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes([self.read_byte()?, self.read_byte()?]))
+    }
+
+    fn read_comments(&mut self) -> Result<Vec<String>, Error> {
+        let mut comments = vec![];
+        let mut comment = vec![];
+        loop {
+            match self.read_byte()? {
+                0 => {
+                    if !comment.is_empty() {
+                        comments.push(String::from_utf8_lossy(&comment).into_owned());
+                        comment.clear();
+                    }
+                }
+                0xff if comment.is_empty() => return Ok(comments),
+                byte => comment.push(byte),
+            }
+        }
+    }
+
+    fn read_frame(&mut self, frame: Frame) -> Result<(), Error> {
+        for _ in 0..frame.data_len() {
+            self.read_byte()?;
+        }
+        if self.read_byte()? != 0 || self.read_byte()? != 0 {
+            return Err(bitstream_err(format!(
+                "Expected 0x0000 after frame data at {:#x}",
+                self.offset
+            )));
+        }
+        Ok(())
+    }
+
+    fn parse(mut self) -> Result<Bitstream, Error> {
+        let mut bitstream = Bitstream::default();
+        // icepack writes an optional comment block, then the preamble
+        let mut start = self.read_u16()?;
+        if u32::from(start) == COMMENT_START {
+            bitstream.comments.extend(self.read_comments()?);
+            start = self.read_u16()?;
+        }
+        let preamble = (u32::from(start) << 16) | u32::from(self.read_u16()?);
+        if preamble != PREAMBLE {
+            return Err(bitstream_err(format!(
+                "No iCE40 preamble at {:#x}",
+                self.offset - 4
+            )));
+        }
+
+        let mut frame = Frame {
+            bank: 0,
+            width: 0,
+            height: 0,
+            offset: 0,
+        };
+        loop {
+            let command = self.read_byte()?;
+            let mut payload = 0usize;
+            for _ in 0..(command & 0xf) {
+                payload = (payload << 8) | usize::from(self.read_byte()?);
+            }
+            match (command >> 4, payload) {
+                (0, 0x01) => {
+                    self.read_frame(frame)?;
+                    bitstream.cram.push(frame);
+                }
+                (0, 0x03) => {
+                    self.read_frame(frame)?;
+                    bitstream.bram.push(frame);
+                }
+                (0, 0x05) => self.crc = 0xffff,
+                (0, 0x06) => break,
+                (0, 0x08) => {
+                    bitstream.reboot = true;
+                    break;
+                }
+                (0, _) => {
+                    return Err(bitstream_err(format!(
+                        "Unknown command payload {payload:#x} at {:#x}",
+                        self.offset
+                    )))
+                }
+                (1, bank) => frame.bank = bank,
+                (2, _) => {
+                    if self.crc != 0 {
+                        return Err(bitstream_err(format!(
+                            "CRC check failed at {:#x}",
+                            self.offset
+                        )));
+                    }
+                    bitstream.crc_checks += 1;
+                }
+                (6, width) => frame.width = width + 1,
+                (7, height) => frame.height = height,
+                (8, offset) => frame.offset = offset,
+                (cmd, payload) => debug!(cmd, payload, "Skipping command"),
+            }
+        }
+        bitstream.len = self.offset;
+        Ok(bitstream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a bitstream with a single CRAM frame, as written by icepack.
+    fn build(width: usize, height: usize) -> Vec<u8> {
+        let mut data = vec![0xff, 0x00];
+        data.extend(b"Lattice\0test\0");
+        data.extend([0x00, 0xff, 0x7e, 0xaa, 0x99, 0x7e]);
+        data.extend([0x51, 0x00, 0x01, 0x05]);
+        let crc_start = data.len();
+        data.extend([0x92, 0x00, 0x20]);
+        let [w1, w0] = u16::try_from(width - 1).unwrap().to_be_bytes();
+        let [h1, h0] = u16::try_from(height).unwrap().to_be_bytes();
+        data.extend([0x62, w1, w0, 0x72, h1, h0, 0x82, 0x00, 0x00, 0x11, 0x00]);
+        data.extend([0x01, 0x01]);
+        data.extend(vec![0x5a; (width * height).div_ceil(8)]);
+        data.extend([0x00, 0x00, 0x22]);
+        let crc = data[crc_start..]
+            .iter()
+            .fold(0xffff, |crc, &b| crc16(crc, b));
+        data.extend(crc.to_be_bytes());
+        data.extend([0x01, 0x06, 0x00]);
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let data = build(872, 272);
+        let bitstream = Bitstream::parse(data.as_slice()).unwrap();
+        assert_eq!(bitstream.comments, vec!["Lattice", "test"]);
+        assert_eq!(bitstream.part(), Some(Part::Hx8k));
+        assert_eq!(bitstream.cram.len(), 1);
+        assert_eq!(bitstream.crc_checks, 1);
+        assert!(!bitstream.reboot);
+        assert_eq!(bitstream.len, data.len() - 1);
+    }
+
+    #[test]
+    fn test_part() {
+        let bitstream = Bitstream::parse(build(332, 144).as_slice()).unwrap();
+        assert_eq!(bitstream.part(), Some(Part::Hx1k));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = build(872, 272);
+        assert!(Bitstream::parse(&data[..data.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_bad_crc() {
+        let mut data = build(872, 272);
+        data[100] ^= 1;
+        assert!(Bitstream::parse(data.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_not_bitstream() {
+        let err = Bitstream::parse(b".comment\n.device 8k\n".as_slice()).unwrap_err();
+        assert_eq!(err.to_string(), "Bitstream Error No iCE40 preamble at 0x0");
+    }
+}
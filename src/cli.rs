@@ -0,0 +1,1002 @@
+//! Command line interface shared by the `icefun`, `icefunprog` and `icefundump` binaries.
+//!
+//! Every command exits with status 0 on success, 1 on error, 2 for invalid
+//! arguments, 3 if a check such as `compare` or `blank-check` fails and
+//! 130 if interrupted by Ctrl-C.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{stderr, stdout, BufWriter, IsTerminal, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use tracing::level_filters::LevelFilter;
+use tracing::{error, info, info_span, warn};
+
+use crate::cmds::ERASE_BLOCK_SIZE;
+use crate::err::Error;
+use crate::{
+    blank_check, erase_range, find_boards, open_board, parse_addr, read_bitstream, Bitstream,
+    BoardArgs, CancelToken, CommonArgs, Device, Digest, DumpFormat, FPGADump, FPGAProg,
+    FlashDevice, FlashGeometry, ImageFormat, LogArgs, LogProgress, Manifest, Multiboot, Part,
+    Programmable, Progress, ProgressReport, Regions,
+};
+
+const CDONE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A check which ran to completion and found a problem, such as
+/// a mismatch or a page which is not blank.
+#[derive(Debug)]
+pub struct CheckFailed(pub String);
+
+impl Display for CheckFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for CheckFailed {}
+
+macro_rules! check_failed {
+    ($($arg:tt)*) => {
+        return Err(CheckFailed(format!($($arg)*)).into())
+    };
+}
+
+fn is_cancelled(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref(), Some(Error::Cancelled(_)))
+}
+
+/// Cancelled by the first Ctrl-C, so that the current operation stops between pages
+/// and the FPGA is released. A second Ctrl-C exits immediately.
+fn cancel_token() -> &'static CancelToken {
+    static CANCEL: OnceLock<CancelToken> = OnceLock::new();
+    CANCEL.get_or_init(|| {
+        let cancel = CancelToken::new();
+        let handler = cancel.clone();
+        let result = ctrlc::set_handler(move || {
+            if handler.is_cancelled() {
+                std::process::exit(130);
+            }
+            handler.cancel();
+        });
+        if let Err(err) = result {
+            warn!("Ctrl-C will not cancel cleanly: {err}");
+        }
+        cancel
+    })
+}
+
+/// Report the result of a command, and convert it to the exit status.
+#[must_use]
+pub fn exit(result: Result<()>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            if err.is::<CheckFailed>() {
+                ExitCode::from(3)
+            } else if is_cancelled(&err) {
+                ExitCode::from(130)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
+
+/// Set up logging and run `command`. In JSON mode, its outcome is reported as the final event.
+fn run_command(log: &LogArgs, command: impl FnOnce() -> Result<()>) -> Result<()> {
+    log.init_logger();
+    let result = command();
+    if log.json {
+        match &result {
+            Ok(()) => info!(result = "pass", "Finished"),
+            Err(err) => {
+                let result = if err.is::<CheckFailed>() {
+                    "fail"
+                } else if is_cancelled(err) {
+                    "cancelled"
+                } else {
+                    "error"
+                };
+                error!(result, error = format!("{err:#}"), "Finished");
+            }
+        }
+    }
+    result
+}
+
+/// Run one phase of a command, reporting when it starts and ends.
+fn phase<T>(name: &'static str, run: impl FnOnce() -> Result<T>) -> Result<T> {
+    info!(phase = name, "Phase start");
+    let start = Instant::now();
+    let result = run();
+    info!(
+        phase = name,
+        ok = result.is_ok(),
+        seconds = start.elapsed().as_secs_f64(),
+        "Phase end"
+    );
+    result
+}
+
+/// Report a digest as an event, and print it unless in JSON mode.
+/// It is printed to stderr if stdout holds dumped data.
+fn report_digest(log: &LogArgs, digest: Digest, to_stderr: bool) {
+    info!(
+        sha256 = digest.sha256_hex(),
+        crc32 = format!("{:08x}", digest.crc32),
+        "Digest"
+    );
+    if to_stderr {
+        eprintln!("{digest}");
+    } else if !log.json {
+        println!("{digest}");
+    }
+}
+
+const BAR_WIDTH: usize = 40;
+const BAR_PERIOD: Duration = Duration::from_millis(100);
+
+/// Draws a progress bar on stderr.
+#[derive(Debug, Default)]
+struct ProgressBar {
+    last_draw: Option<Instant>,
+}
+
+impl Progress for ProgressBar {
+    #[allow(clippy::cast_precision_loss)]
+    fn report(&mut self, report: &ProgressReport) {
+        let now = Instant::now();
+        let finished = report.is_finished();
+        if !finished
+            && self
+                .last_draw
+                .is_some_and(|last| now.duration_since(last) < BAR_PERIOD)
+        {
+            return;
+        }
+        let percent = report.percent();
+        let filled = BAR_WIDTH * percent / 100;
+        eprint!(
+            "\r{:12} [{}{}] {percent:3}% {:8.1} KiB/s",
+            report.phase.to_string(),
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            report.throughput() / 1024.0,
+        );
+        if finished {
+            eprintln!();
+            self.last_draw = None;
+        } else {
+            self.last_draw = Some(now);
+        }
+    }
+}
+
+/// A progress bar when stderr is a terminal, otherwise progress is logged.
+fn progress(log: &LogArgs) -> Box<dyn Progress + Send> {
+    if !log.json && log.log_level != LevelFilter::OFF && stderr().is_terminal() {
+        Box::new(ProgressBar::default())
+    } else {
+        Box::new(LogProgress::default())
+    }
+}
+
+/// Number of bytes to read.
+#[derive(Copy, Clone, Debug)]
+enum Size {
+    Bytes(usize),
+    /// Up to the end of the flash
+    All,
+}
+
+impl Size {
+    fn bytes(self, fpga: &impl FlashDevice, offset: usize) -> usize {
+        match self {
+            Size::Bytes(size) => size,
+            Size::All => fpga.geometry().capacity.saturating_sub(offset),
+        }
+    }
+}
+
+fn parse_size(arg: &str) -> Result<Size> {
+    if arg == "all" {
+        Ok(Size::All)
+    } else {
+        Ok(Size::Bytes(parse_addr(arg)?))
+    }
+}
+
+fn parse_align(arg: &str) -> Result<usize> {
+    let align = parse_addr(arg)?;
+    if align == 0 || align % ERASE_BLOCK_SIZE != 0 {
+        anyhow::bail!("{align:#x} is not a multiple of the {ERASE_BLOCK_SIZE:#x} byte erase block");
+    }
+    Ok(align)
+}
+
+fn check_sha256(digest: Digest, expected: Option<&String>) -> Result<()> {
+    if let Some(expected) = expected {
+        if !digest.matches_sha256(expected) {
+            check_failed!("SHA-256 {} does not match {expected}", digest.sha256_hex());
+        }
+    }
+    Ok(())
+}
+
+/// Programming tool for Devantech iceFUN board.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+impl Cli {
+    /// # Errors
+    ///
+    /// Will return `Err` if the command fails.
+    pub fn run(self) -> Result<()> {
+        match self.command {
+            Command::Program(args) => args.run(),
+            Command::Verify(args) => args.run(false),
+            Command::Dump(args) => args.run(),
+            Command::Erase(args) => args.run(),
+            Command::Info(args) => info(&args),
+            Command::BlankCheck(args) => args.run(),
+            Command::Compare(args) => args.run(true),
+            Command::Reset(args) => reset(&args),
+            Command::List(args) => list(&args),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Erase, program and verify the flash
+    Program(ProgramArgs),
+    /// Verify the flash against an input file, with the board's verify command
+    Verify(ImageArgs),
+    /// Read the flash to a file
+    Dump(DumpArgs),
+    /// Erase the flash
+    Erase(EraseArgs),
+    /// Show the firmware version and flash chip
+    Info(BoardArgs),
+    /// Check that the flash is erased
+    BlankCheck(BlankCheckArgs),
+    /// Compare the flash with an input file, without erasing or programming
+    Compare(ImageArgs),
+    /// Reset the FPGA, so that it configures from the flash
+    Reset(BoardArgs),
+    /// List attached iceFUN boards, with their USB serial numbers and firmware versions
+    List(LogArgs),
+}
+
+/// How an input file is placed in the flash.
+#[derive(clap::Args, Debug)]
+struct InputArgs {
+    /// Input file format, detected from the file extension by default
+    #[arg(long)]
+    format: Option<ImageFormat>,
+
+    /// Address in hex and ELF input files which is programmed at the offset
+    #[arg(long, default_value = "0", value_parser = parse_addr)]
+    base: usize,
+}
+
+impl InputArgs {
+    fn format(&self, input: &Path) -> ImageFormat {
+        self.format.unwrap_or_else(|| ImageFormat::from_path(input))
+    }
+}
+
+/// Programming tool for Devantech iceFUN board.
+#[derive(Parser, Debug)]
+#[allow(clippy::struct_excessive_bools)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
+pub struct ProgramArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    /// Skip verification
+    #[arg(short = 'v', long)]
+    skip_verification: bool,
+
+    /// Erase the whole flash chip instead of only the sectors covered by the input
+    #[arg(long)]
+    erase_all: bool,
+
+    /// Preserve flash contents outside the input that share its erase blocks
+    #[arg(long, conflicts_with = "erase_all")]
+    preserve: bool,
+
+    /// Only erase and program erase blocks which differ from the input
+    #[arg(long, conflicts_with = "erase_all")]
+    incremental: bool,
+
+    /// Compare the flash with the input, without erasing or programming
+    #[arg(long, conflicts_with_all = ["erase_all", "preserve", "incremental"])]
+    compare: bool,
+
+    /// Program every attached board at the same time
+    #[arg(long, conflicts_with_all = ["port", "serial", "index"])]
+    all_boards: bool,
+
+    /// Program the input even if it is not a valid HX8K bitstream
+    #[arg(long)]
+    force: bool,
+
+    #[command(flatten)]
+    input_args: InputArgs,
+
+    /// Fail before touching the flash unless the SHA-256 of the image matches this hex digest
+    #[arg(long, value_name = "HEX", conflicts_with = "compare")]
+    expect_sha256: Option<String>,
+
+    /// Input file to program
+    #[arg(value_name = "INPUT", required_unless_present = "erase_all")]
+    input: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<ProgramCommand>,
+}
+
+#[derive(Subcommand, Debug)]
+enum ProgramCommand {
+    /// Program up to four bitstreams behind a warmboot header, like icemulti
+    Multiboot {
+        /// Align images to a multiple of this many bytes, which must be whole erase blocks
+        #[arg(short, long, default_value = "64K", value_parser = parse_align)]
+        align: usize,
+
+        /// Set the cold boot flag, so that the CBSEL pins select the image at power on
+        #[arg(short, long)]
+        coldboot: bool,
+
+        /// Index of the image booted at power on
+        #[arg(long, default_value = "0")]
+        por_image: usize,
+
+        /// Bitstreams to program
+        #[arg(value_name = "IMAGE", required = true, num_args = 1..=4)]
+        images: Vec<PathBuf>,
+    },
+
+    /// Program several files at the offsets listed in a JSON manifest
+    Manifest {
+        /// Manifest file
+        #[arg(value_name = "MANIFEST")]
+        manifest: PathBuf,
+    },
+}
+
+fn check_bitstream(reader: impl Read, force: bool) -> Result<()> {
+    let problem = match Bitstream::parse(reader) {
+        Ok(bitstream) => {
+            info!(comments = ?bitstream.comments, part = ?bitstream.part(), "Bitstream");
+            if bitstream.crc_checks == 0 {
+                warn!("Bitstream has no CRC check");
+            }
+            match bitstream.part() {
+                Some(Part::Hx8k) => return Ok(()),
+                Some(part) => format!("Bitstream is for {part}, not {}", Part::Hx8k),
+                None => "Bitstream has no CRAM data".to_string(),
+            }
+        }
+        Err(err) => err.to_string(),
+    };
+    if force {
+        warn!(problem, "Programming anyway");
+        Ok(())
+    } else {
+        anyhow::bail!("{problem}, use --force to program anyway")
+    }
+}
+
+impl ProgramArgs {
+    /// # Errors
+    ///
+    /// Will return `Err` if programming fails.
+    pub fn run(self) -> Result<()> {
+        run_command(&self.common.board.log, || {
+            let offset = self.common.offset;
+            match &self.command {
+                Some(ProgramCommand::Multiboot {
+                    align,
+                    coldboot,
+                    por_image,
+                    images,
+                }) => {
+                    if offset != 0 {
+                        anyhow::bail!(
+                            "Multiboot images are programmed at address 0, where the FPGA boots, \
+                             so --offset is not supported"
+                        );
+                    }
+                    let mut multiboot = Multiboot::new(*align);
+                    multiboot.coldboot = *coldboot;
+                    multiboot.por_image = *por_image;
+                    for image in images {
+                        let data = std::fs::read(image)?;
+                        check_bitstream(data.as_slice(), self.force)?;
+                        multiboot.add_image(data)?;
+                    }
+                    let data = multiboot.build(0)?;
+                    self.check_input(data.as_slice())?;
+                    self.program_boards(|_| Ok(vec![FPGAProg::from_bytes(data.clone(), 0)]))
+                }
+                Some(ProgramCommand::Manifest { manifest }) => {
+                    let regions = Manifest::from_path(manifest)?
+                        .load(manifest.parent().unwrap_or(Path::new(".")))?;
+                    self.check_regions(&regions)?;
+                    self.program_boards(|geometry| Ok(regions.programmers(geometry)?))
+                }
+                None => match &self.input {
+                    Some(input) => match self.input_args.format(input) {
+                        ImageFormat::Bin => {
+                            if !self.compare {
+                                check_bitstream(File::open(input)?, self.force)?;
+                            }
+                            self.check_input(File::open(input)?)?;
+                            self.program_boards(|_| Ok(vec![FPGAProg::from_path(input, offset)?]))
+                        }
+                        format => {
+                            let regions = format.load(input, self.input_args.base, offset)?;
+                            self.check_regions(&regions)?;
+                            self.program_boards(|geometry| Ok(regions.programmers(geometry)?))
+                        }
+                    },
+                    None => self.program_boards(|_| Ok(Vec::<FPGAProg<File>>::new())),
+                },
+            }
+        })
+    }
+
+    /// Fail unless `--expect-sha256` matches the input, before any board is opened.
+    fn check_input(&self, input: impl Read) -> Result<()> {
+        match &self.expect_sha256 {
+            Some(expected) => check_sha256(Digest::from_reader(input)?, Some(expected)),
+            None => Ok(()),
+        }
+    }
+
+    fn check_regions(&self, regions: &Regions) -> Result<()> {
+        if self.expect_sha256.is_none() {
+            return Ok(());
+        }
+        match regions.regions() {
+            [region] => self.check_input(region.data.as_slice()),
+            regions => anyhow::bail!(
+                "--expect-sha256 needs a single image, the input has {} regions",
+                regions.len()
+            ),
+        }
+    }
+
+    /// Program the selected board, or every board at once on separate threads.
+    fn program_boards<R: Read + Seek>(
+        &self,
+        plan: impl Fn(&FlashGeometry) -> Result<Vec<FPGAProg<R>>> + Sync,
+    ) -> Result<()> {
+        if !self.all_boards {
+            let dev = Device::new(self.common.board.open_port()?);
+            for digest in self.program(dev, &plan)? {
+                report_digest(&self.common.board.log, digest, false);
+            }
+            return Ok(());
+        }
+        let boards = find_boards()?;
+        if boards.is_empty() {
+            anyhow::bail!("No port");
+        }
+        info!(boards = boards.len(), "Programming boards");
+        let results: Vec<Result<Vec<Digest>>> = thread::scope(|scope| {
+            let handles: Vec<_> = boards
+                .iter()
+                .map(|board| {
+                    let plan = &plan;
+                    scope.spawn(move || {
+                        let _span = info_span!("board", port = board.port).entered();
+                        let dev = Device::new(open_board(&board.port)?);
+                        let result = self.program(dev, plan);
+                        if let Err(err) = &result {
+                            error!(%err, "Failed");
+                        }
+                        result
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("Programming thread panicked")))
+                })
+                .collect()
+        });
+
+        let width = boards
+            .iter()
+            .map(|board| board.port.len())
+            .max()
+            .unwrap_or_default();
+        let mut failed = 0;
+        for (board, result) in boards.iter().zip(results) {
+            let serial_number = board.serial_number.as_deref().unwrap_or("-");
+            if result.is_err() {
+                failed += 1;
+            }
+            if self.common.board.log.json {
+                match result {
+                    Ok(digests) => {
+                        let sha256: Vec<String> = digests.iter().map(Digest::sha256_hex).collect();
+                        info!(port = board.port, serial_number, ?sha256, "Board passed");
+                    }
+                    Err(err) => {
+                        let error = format!("{err:#}");
+                        error!(port = board.port, serial_number, error, "Board failed");
+                    }
+                }
+                continue;
+            }
+            let outcome = match result {
+                Ok(digests) => digests.iter().fold("PASS".to_string(), |outcome, digest| {
+                    format!("{outcome} {digest}")
+                }),
+                Err(err) => format!("FAIL {err}"),
+            };
+            println!("{:width$}  {serial_number:16}  {outcome}", board.port);
+        }
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} boards failed", boards.len());
+        }
+        Ok(())
+    }
+
+    /// Returns the digest of each image programmed.
+    fn program<R: Read + Seek>(
+        &self,
+        dev: Device,
+        plan: impl Fn(&FlashGeometry) -> Result<Vec<FPGAProg<R>>>,
+    ) -> Result<Vec<Digest>> {
+        let (_, mut fpga) = dev.prepare()?;
+        let mut programmers = plan(fpga.geometry())?;
+        for programmer in &mut programmers {
+            programmer.check(&fpga)?;
+            programmer.set_cancel(cancel_token().clone());
+            if !self.all_boards {
+                programmer.set_progress(progress(&self.common.board.log));
+            }
+        }
+        if self.compare {
+            phase("compare", || compare(&mut programmers, &mut fpga))?;
+            return Ok(vec![]);
+        }
+        if self.erase_all {
+            phase("erase", || Ok(fpga.erase_chip()?))?;
+        } else {
+            if self.preserve {
+                phase("preserve", || {
+                    for programmer in &mut programmers {
+                        programmer.preserve(&mut fpga)?;
+                    }
+                    Ok(())
+                })?;
+            }
+            if self.incremental {
+                phase("skip_unchanged", || {
+                    for programmer in &mut programmers {
+                        programmer.skip_unchanged(&mut fpga)?;
+                    }
+                    Ok(())
+                })?;
+            }
+            phase("erase", || {
+                for programmer in &mut programmers {
+                    programmer.erase(&mut fpga)?;
+                }
+                Ok(())
+            })?;
+        }
+        phase("program", || {
+            for programmer in &mut programmers {
+                programmer.program(&mut fpga)?;
+            }
+            Ok(())
+        })?;
+        if !self.skip_verification {
+            phase("verify", || {
+                for programmer in &mut programmers {
+                    programmer.verify(&mut fpga)?;
+                }
+                Ok(())
+            })?;
+        }
+        let digests: Vec<Digest> = programmers.iter().filter_map(FPGAProg::digest).collect();
+        if !programmers.is_empty() {
+            let mut dev = fpga.release()?;
+            if !dev.wait_cdone(CDONE_TIMEOUT)? {
+                check_failed!("FPGA did not configure, CDONE is low");
+            }
+        }
+
+        Ok(digests)
+    }
+}
+
+fn compare<R: Read + Seek>(
+    programmers: &mut [FPGAProg<R>],
+    fpga: &mut crate::dev::DeviceInReset,
+) -> Result<()> {
+    let mut differ = 0;
+    for programmer in programmers {
+        for mismatch in programmer.compare(fpga)? {
+            warn!(%mismatch, "Mismatch");
+            differ += mismatch.expected.len();
+        }
+    }
+    if differ > 0 {
+        check_failed!("{differ} bytes differ");
+    }
+    info!("Flash matches");
+    Ok(())
+}
+
+/// An input file to check the flash against.
+#[derive(clap::Args, Debug)]
+struct ImageArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    #[command(flatten)]
+    input_args: InputArgs,
+
+    /// Input file
+    #[arg(value_name = "INPUT")]
+    input: PathBuf,
+}
+
+impl ImageArgs {
+    /// Compare the flash with the input, or verify it if `compare` is false.
+    fn run(self, compare: bool) -> Result<()> {
+        run_command(&self.common.board.log, || {
+            let regions = self.input_args.format(&self.input).load(
+                &self.input,
+                self.input_args.base,
+                self.common.offset,
+            )?;
+            let port = self.common.board.open_port()?;
+            let (_, mut fpga) = Device::new(port).prepare()?;
+            let mut programmers = regions.programmers(fpga.geometry())?;
+            for programmer in &mut programmers {
+                programmer.set_progress(progress(&self.common.board.log));
+                programmer.set_cancel(cancel_token().clone());
+            }
+            if compare {
+                return phase("compare", || self::compare(&mut programmers, &mut fpga));
+            }
+            phase("verify", || {
+                for programmer in &mut programmers {
+                    programmer.verify_all(&mut fpga)?;
+                }
+                Ok(())
+            })?;
+            info!("Flash verified");
+            Ok(())
+        })
+    }
+}
+
+/// Flash reading tool for Devantech iceFUN board.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct DumpArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    /// Read size, or `all` to read up to the end of the flash
+    #[arg(short, long, default_value = "0", value_parser = parse_size)]
+    size: Size,
+
+    /// Read the length of the bitstream stored at the offset
+    #[arg(short, long, conflicts_with_all = ["size", "blank_check"])]
+    auto_size: bool,
+
+    /// Output format
+    #[arg(short, long, default_value = "raw")]
+    format: DumpFormat,
+
+    /// Check that the flash is erased instead of dumping it.
+    /// A size of 0 checks up to the end of the flash.
+    #[arg(short, long, conflicts_with = "output")]
+    blank_check: bool,
+
+    /// Fail unless the SHA-256 of the dumped bytes matches this hex digest
+    #[arg(long, value_name = "HEX", conflicts_with = "blank_check")]
+    expect_sha256: Option<String>,
+
+    /// Output file, or `-` for stdout
+    #[arg(value_name = "OUTPUT", required_unless_present = "blank_check")]
+    output: Option<PathBuf>,
+}
+
+impl DumpArgs {
+    /// # Errors
+    ///
+    /// Will return `Err` if dumping fails.
+    pub fn run(self) -> Result<()> {
+        run_command(&self.common.board.log, || {
+            let port = self.common.board.open_port()?;
+            let (_, mut fpga) = Device::new(port).prepare()?;
+            let offset = self.common.offset;
+            let size = if self.auto_size {
+                read_bitstream(&mut fpga, offset)?.len
+            } else {
+                self.size.bytes(&fpga, offset)
+            };
+            if self.blank_check {
+                return check_blank(
+                    &self.common.board.log,
+                    &mut fpga,
+                    offset,
+                    (size > 0).then_some(size),
+                );
+            }
+            let output = self.output.as_ref().expect("required unless blank check");
+            let to_stdout = output.as_os_str() == "-";
+            if to_stdout && self.common.board.log.json {
+                anyhow::bail!("--json writes events to stdout, so dump to a file");
+            }
+            let writer: Box<dyn Write> = if to_stdout {
+                Box::new(stdout().lock())
+            } else {
+                Box::new(BufWriter::new(File::create(output)?))
+            };
+            let mut dumper = FPGADump::new(writer, offset, size, self.format);
+            dumper.set_progress(progress(&self.common.board.log));
+            dumper.set_cancel(cancel_token().clone());
+            phase("dump", || Ok(dumper.dump(&mut fpga)?))?;
+            let digest = dumper.digest().expect("set by dump");
+            report_digest(&self.common.board.log, digest, to_stdout);
+            check_sha256(digest, self.expect_sha256.as_ref())
+        })
+    }
+}
+
+fn check_blank(
+    log: &LogArgs,
+    fpga: &mut crate::dev::DeviceInReset,
+    offset: usize,
+    size: Option<usize>,
+) -> Result<()> {
+    let not_blank = phase("blank_check", || {
+        let mut progress = progress(log);
+        Ok(blank_check(
+            fpga,
+            offset,
+            size,
+            progress.as_mut(),
+            cancel_token(),
+        )?)
+    })?;
+    if !not_blank.is_empty() {
+        check_failed!("{} pages are not blank", not_blank.len());
+    }
+    Ok(())
+}
+
+#[derive(clap::Args, Debug)]
+struct BlankCheckArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    /// Check size
+    #[arg(short, long, default_value = "all", value_parser = parse_size)]
+    size: Size,
+}
+
+impl BlankCheckArgs {
+    fn run(self) -> Result<()> {
+        run_command(&self.common.board.log, || {
+            let port = self.common.board.open_port()?;
+            let (_, mut fpga) = Device::new(port).prepare()?;
+            let size = self.size.bytes(&fpga, self.common.offset);
+            check_blank(
+                &self.common.board.log,
+                &mut fpga,
+                self.common.offset,
+                Some(size),
+            )
+        })
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct EraseArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    /// Erase the blocks covering this many bytes, or `all` up to the end of the flash
+    #[arg(short, long, value_parser = parse_size, required_unless_present = "chip")]
+    size: Option<Size>,
+
+    /// Erase the whole flash chip
+    #[arg(long, conflicts_with = "size")]
+    chip: bool,
+}
+
+impl EraseArgs {
+    fn run(self) -> Result<()> {
+        run_command(&self.common.board.log, || {
+            let port = self.common.board.open_port()?;
+            let (_, mut fpga) = Device::new(port).prepare()?;
+            phase("erase", || {
+                match self.size {
+                    Some(size) => {
+                        let size = size.bytes(&fpga, self.common.offset);
+                        let mut progress = progress(&self.common.board.log);
+                        erase_range(
+                            &mut fpga,
+                            self.common.offset,
+                            size,
+                            progress.as_mut(),
+                            cancel_token(),
+                        )?;
+                    }
+                    None => fpga.erase_chip()?,
+                }
+                Ok(())
+            })
+        })
+    }
+}
+
+fn info(board: &BoardArgs) -> Result<()> {
+    run_command(&board.log, || {
+        let mut dev = Device::new(board.open_port()?);
+        let version = dev.version()?;
+        let (flash_id, fpga) = dev.reset_fpga()?;
+        let geometry = *fpga.geometry();
+        let cdone = fpga.release()?.wait_cdone(CDONE_TIMEOUT)?;
+        if board.log.json {
+            info!(
+                version,
+                %flash_id,
+                capacity = geometry.capacity,
+                page_size = geometry.page_size,
+                block_size = geometry.block_size,
+                cdone,
+                "Info"
+            );
+            return Ok(());
+        }
+        println!("Firmware: v{version}");
+        println!("Flash:    {flash_id}");
+        println!(
+            "Capacity: {} bytes, {} byte pages, {} byte erase blocks",
+            geometry.capacity, geometry.page_size, geometry.block_size
+        );
+        println!("CDONE:    {}", if cdone { "high" } else { "low" });
+        Ok(())
+    })
+}
+
+fn reset(board: &BoardArgs) -> Result<()> {
+    run_command(&board.log, || {
+        let port = board.open_port()?;
+        let (_, fpga) = Device::new(port).prepare()?;
+        if !fpga.release()?.wait_cdone(CDONE_TIMEOUT)? {
+            check_failed!("FPGA did not configure, CDONE is low");
+        }
+        Ok(())
+    })
+}
+
+fn list(log: &LogArgs) -> Result<()> {
+    run_command(log, || {
+        let boards = find_boards()?;
+        let width = boards
+            .iter()
+            .map(|board| board.port.len())
+            .chain(["PORT".len()])
+            .max()
+            .unwrap_or_default();
+        if !log.json {
+            println!(
+                "INDEX  {:width$}  {:16}  FIRMWARE  PRODUCT",
+                "PORT", "SERIAL"
+            );
+        }
+        for (index, board) in boards.into_iter().enumerate() {
+            let firmware =
+                match open_board(&board.port).and_then(|port| Ok(Device::new(port).version()?)) {
+                    Ok(version) => Some(version),
+                    Err(err) => {
+                        warn!(port = board.port, %err, "Reading firmware version failed");
+                        None
+                    }
+                };
+            if log.json {
+                info!(
+                    index,
+                    port = board.port,
+                    serial_number = board.serial_number,
+                    manufacturer = board.manufacturer,
+                    product = board.product,
+                    firmware,
+                    "Board"
+                );
+                continue;
+            }
+            let version = firmware.map_or_else(|| "?".to_string(), |version| format!("v{version}"));
+            let product = [board.manufacturer, board.product]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" ");
+            println!(
+                "{index:5}  {:width$}  {:16}  {version:8}  {product}",
+                board.port,
+                board.serial_number.as_deref().unwrap_or("-"),
+            );
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+        ProgramArgs::command().debug_assert();
+        DumpArgs::command().debug_assert();
+        let parse = |args: &[&str]| Cli::try_parse_from([&["icefun"], args].concat());
+        assert!(parse(&["program", "top.bin", "--incremental"]).is_ok());
+        assert!(parse(&["program", "--erase-all"]).is_ok());
+        assert!(parse(&["program", "multiboot", "a.bin", "b.bin"]).is_ok());
+        assert!(parse(&["program", "multiboot", "-a", "128K", "a.bin"]).is_ok());
+        assert!(parse(&["program", "multiboot", "-a", "0", "a.bin"]).is_err());
+        assert!(parse(&["program", "multiboot", "-a", "4K", "a.bin"]).is_err());
+        assert!(parse(&["program"]).is_err());
+        assert!(parse(&["program", "--all-boards", "top.bin"]).is_ok());
+        assert!(parse(&["program", "--all-boards", "--serial", "1", "top.bin"]).is_err());
+        assert!(parse(&["erase"]).is_err());
+        assert!(parse(&["erase", "-s", "all"]).is_ok());
+        assert!(parse(&["blank-check", "-o", "64K", "-s", "4K"]).is_ok());
+        assert!(parse(&["dump", "-a", "-s", "1K", "out.bin"]).is_err());
+        assert!(parse(&["list", "--json"]).is_ok());
+        assert!(parse(&["list", "--port", "/dev/ttyACM0"]).is_err());
+        assert!(parse(&["list", "-o", "64K"]).is_err());
+        assert!(parse(&["info", "--serial", "1", "-l", "debug"]).is_ok());
+        assert!(parse(&["info", "-o", "64K"]).is_err());
+        assert!(parse(&["reset", "-o", "64K"]).is_err());
+        assert!(parse(&["dump", "--json", "-s", "1K", "out.bin"]).is_ok());
+    }
+
+    #[test]
+    fn test_exit() {
+        assert_eq!(exit(Ok(())), ExitCode::SUCCESS);
+        assert_eq!(exit(Err(anyhow::anyhow!("failed"))), ExitCode::FAILURE);
+        assert_eq!(
+            exit(Err(CheckFailed("mismatch".into()).into())),
+            ExitCode::from(3)
+        );
+        assert_eq!(
+            exit(Err(Error::Cancelled("program".into()).into())),
+            ExitCode::from(130)
+        );
+    }
+}
@@ -1,5 +1,14 @@
+//! The commands understood by the iceFUN firmware.
+//!
+//! Each command is a [`Command`] constant which writes its opcode and arguments
+//! to a [`SerialPort`], then reads a fixed size reply. Most users want the
+//! page level operations of [`crate::DeviceInReset`] instead.
+//!
+//! [`CmdArgs`] and [`CmdReply`] are sealed, so only the types here implement them.
+
 use std::{
     fmt::{Debug, Display},
+    io::Write,
     marker::PhantomData,
 };
 
@@ -8,42 +17,85 @@ use tracing::instrument;
 use crate::err::Error;
 use crate::serialport::SerialPort;
 
-pub(crate) const PAGE_SIZE: usize = 256;
-pub(crate) const CMD_GET_VER: Command<(), GetVerReply> = Command::new(0xb1);
-pub(crate) const CMD_RESET: Command<(), [u8; 3]> = Command::new(0xb2);
-pub(crate) const CMD_ERASE_64K: Command<[u8; 1], ()> = Command::new(0xb4);
-pub(crate) const CMD_PROGRAM_PAGE: Command<ProgData, ProgResult> = Command::new(0xb5);
-pub(crate) const CMD_READ_PAGE: Command<ReadData, ReadResult> = Command::new(0xb6);
-pub(crate) const CMD_VERIFY_PAGE: Command<ProgData, ProgResult> = Command::new(0xb7);
-pub(crate) const CMD_RELEASE_FPGA: Command<(), ()> = Command::new(0xb9);
+/// Bytes in a flash page, the unit of programming and reading.
+pub const PAGE_SIZE: usize = 256;
+/// Bytes in an erase block.
+pub const ERASE_BLOCK_SIZE: usize = 64 * 1024;
+/// Bytes of flash which can be addressed, as the firmware takes 24 bit addresses.
+pub const ADDRESS_SPACE: usize = 1 << 24;
+/// Read the firmware version.
+pub const CMD_GET_VER: Command<(), GetVerReply> = Command::new(0xb1);
+/// Hold the FPGA in reset, and read the flash ID.
+pub const CMD_RESET: Command<(), [u8; 3]> = Command::new(0xb2);
+/// Erase the whole flash.
+pub const CMD_ERASE_CHIP: Command<(), ()> = Command::new(0xb3);
+/// Erase the 64KiB block with the given index.
+pub const CMD_ERASE_64K: Command<[u8; 1], ()> = Command::new(0xb4);
+/// Program one page.
+pub const CMD_PROGRAM_PAGE: Command<ProgData, ProgResult> = Command::new(0xb5);
+/// Read one page.
+pub const CMD_READ_PAGE: Command<ReadData, ReadResult> = Command::new(0xb6);
+/// Compare one page with the flash.
+pub const CMD_VERIFY_PAGE: Command<ProgData, ProgResult> = Command::new(0xb7);
+/// Read the FPGA CDONE pin.
+pub const CMD_GET_CDONE: Command<(), [u8; 1]> = Command::new(0xb8);
+/// Release the FPGA from reset, so it configures from the flash.
+pub const CMD_RELEASE_FPGA: Command<(), ()> = Command::new(0xb9);
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for () {}
+    impl<const LEN: usize> Sealed for [u8; LEN] {}
+    impl Sealed for super::ProgData<'_> {}
+    impl Sealed for super::ReadData {}
+    impl Sealed for super::GetVerReply {}
+    impl Sealed for super::ProgResult {}
+    impl Sealed for super::ReadResult {}
+}
+
+/// The 24 bit address sent to the firmware.
+fn addr_bytes(addr: usize) -> Result<[u8; 3], Error> {
+    if addr >= ADDRESS_SPACE {
+        return Err(Error::Range(format!(
+            "Address {addr:#x} does not fit in 24 bits"
+        )));
+    }
+    let [_, hi, mid, lo] = u32::try_from(addr)?.to_be_bytes();
+    Ok([hi, mid, lo])
+}
 
-pub(crate) trait CmdArgs: Debug {
-    fn send_args(&self, port: &mut Box<dyn SerialPort>) -> Result<(), Error>;
+/// Arguments written after a command's opcode.
+pub trait CmdArgs: sealed::Sealed + Debug {
+    /// Write the arguments to `port`.
+    fn send_args(&self, port: &mut dyn Write) -> Result<(), Error>;
 }
 
 impl CmdArgs for () {
-    fn send_args(&self, _port: &mut Box<dyn SerialPort>) -> Result<(), Error> {
+    fn send_args(&self, _port: &mut dyn Write) -> Result<(), Error> {
         // Sends zero bytes
         Ok(())
     }
 }
 
 impl<const LEN: usize> CmdArgs for [u8; LEN] {
-    fn send_args(&self, port: &mut Box<dyn SerialPort>) -> Result<(), Error> {
+    fn send_args(&self, port: &mut dyn Write) -> Result<(), Error> {
         port.write_all(self)?;
         Ok(())
     }
 }
 
-pub(crate) trait CmdReply: Debug
+/// The reply read after a command's arguments.
+pub trait CmdReply: sealed::Sealed + Debug
 where
     Self: Sized,
 {
-    fn receive_reply(port: &mut Box<dyn SerialPort>) -> Result<Self, Error>;
+    /// Read the reply from `port`, failing if the firmware reports an error.
+    fn receive_reply(port: &mut dyn SerialPort) -> Result<Self, Error>;
 }
 
 impl CmdReply for () {
-    fn receive_reply(port: &mut Box<dyn SerialPort>) -> Result<Self, Error> {
+    fn receive_reply(port: &mut dyn SerialPort) -> Result<Self, Error> {
         let mut buf = [0u8];
         port.read_exact(&mut buf)?;
         Ok(())
@@ -51,14 +103,15 @@ impl CmdReply for () {
 }
 
 impl<const LEN: usize> CmdReply for [u8; LEN] {
-    fn receive_reply(port: &mut Box<dyn SerialPort>) -> Result<Self, Error> {
+    fn receive_reply(port: &mut dyn SerialPort) -> Result<Self, Error> {
         let mut buf = [0u8; LEN];
         port.read_exact(&mut buf)?;
         Ok(buf)
     }
 }
 
-pub(crate) struct Command<Args: CmdArgs, Reply: CmdReply> {
+/// A firmware command, typed by its arguments and reply.
+pub struct Command<Args: CmdArgs, Reply: CmdReply> {
     cmd: u8,
     _args: PhantomData<Args>,
     _reply: PhantomData<Reply>,
@@ -79,35 +132,35 @@ impl<Args: CmdArgs, Reply: CmdReply> Command<Args, Reply> {
         }
     }
 
+    /// Send the command with `args`, and read its reply.
+    /// Nothing is sent if the arguments are invalid.
     #[instrument(skip(port))]
-    pub(crate) fn run_args(
-        &self,
-        port: &mut Box<dyn SerialPort>,
-        args: &Args,
-    ) -> Result<Reply, Error> {
-        port.write_all(&[self.cmd])?;
-        args.send_args(port)?;
+    pub fn run_args(&self, port: &mut dyn SerialPort, args: &Args) -> Result<Reply, Error> {
+        let mut message = vec![self.cmd];
+        args.send_args(&mut message)?;
+        port.write_all(&message)?;
         Reply::receive_reply(port)
     }
 }
 
 impl<Reply: CmdReply> Command<(), Reply> {
+    /// Send a command which takes no arguments, and read its reply.
     #[instrument(skip(port))]
-    pub(crate) fn run(&self, port: &mut Box<dyn SerialPort>) -> Result<Reply, Error> {
+    pub fn run(&self, port: &mut dyn SerialPort) -> Result<Reply, Error> {
         self.run_args(port, &())
     }
 }
 
+/// A page to program or verify, padded with zeros to [`PAGE_SIZE`].
 #[derive(Debug)]
-pub(crate) struct ProgData<'a> {
+pub struct ProgData<'a> {
     pub addr: usize,
     pub data: &'a [u8],
 }
 
 impl CmdArgs for ProgData<'_> {
-    fn send_args(&self, port: &mut Box<dyn SerialPort>) -> Result<(), Error> {
-        let addr_bytes = self.addr.to_be_bytes();
-        port.write_all(&addr_bytes[5..])?;
+    fn send_args(&self, port: &mut dyn Write) -> Result<(), Error> {
+        port.write_all(&addr_bytes(self.addr)?)?;
         let (data_seg, pad_len) = if self.data.len() > PAGE_SIZE {
             (&self.data[..PAGE_SIZE], 0)
         } else {
@@ -121,8 +174,9 @@ impl CmdArgs for ProgData<'_> {
     }
 }
 
+/// The firmware version.
 #[derive(Debug)]
-pub(crate) struct GetVerReply(pub u8);
+pub struct GetVerReply(pub u8);
 
 impl Display for GetVerReply {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -131,7 +185,7 @@ impl Display for GetVerReply {
 }
 
 impl CmdReply for GetVerReply {
-    fn receive_reply(port: &mut Box<dyn SerialPort>) -> Result<Self, Error> {
+    fn receive_reply(port: &mut dyn SerialPort) -> Result<Self, Error> {
         let mut buf = [0u8; 2];
         port.read_exact(&mut buf)?;
         if buf[0] == 38 {
@@ -142,11 +196,13 @@ impl CmdReply for GetVerReply {
     }
 }
 
+/// Success of programming or verifying a page.
+/// A failure is returned as [`Error::Cmd`] instead.
 #[derive(Debug)]
-pub(crate) struct ProgResult;
+pub struct ProgResult;
 
 impl CmdReply for ProgResult {
-    fn receive_reply(port: &mut Box<dyn SerialPort>) -> Result<Self, Error> {
+    fn receive_reply(port: &mut dyn SerialPort) -> Result<Self, Error> {
         let mut reply = [0u8; 4];
         port.read_exact(&mut reply)?;
         if reply[0] == 0 {
@@ -161,25 +217,26 @@ impl CmdReply for ProgResult {
     }
 }
 
+/// The page to read.
 #[derive(Debug)]
-pub(crate) struct ReadData {
+pub struct ReadData {
     /// address in bytes
     pub addr: usize,
 }
 
 impl CmdArgs for ReadData {
-    fn send_args(&self, port: &mut Box<dyn SerialPort>) -> Result<(), Error> {
-        let addr_bytes = self.addr.to_be_bytes();
-        port.write_all(&addr_bytes[5..])?;
+    fn send_args(&self, port: &mut dyn Write) -> Result<(), Error> {
+        port.write_all(&addr_bytes(self.addr)?)?;
         Ok(())
     }
 }
 
+/// The contents of a page.
 #[derive(Debug)]
-pub(crate) struct ReadResult(pub [u8; PAGE_SIZE]);
+pub struct ReadResult(pub [u8; PAGE_SIZE]);
 
 impl CmdReply for ReadResult {
-    fn receive_reply(port: &mut Box<dyn SerialPort>) -> Result<Self, Error> {
+    fn receive_reply(port: &mut dyn SerialPort) -> Result<Self, Error> {
         let mut rr = ReadResult([0; PAGE_SIZE]);
         port.read_exact(&mut rr.0)?;
         Ok(rr)
@@ -211,6 +268,12 @@ mod tests {
         assert_eq!(reply, [1, 2, 3]);
     }
 
+    #[test]
+    fn test_erase_chip() {
+        let (port, ()) = CMD_ERASE_CHIP.test_ok(vec![0], &());
+        assert_eq!(port.written(), vec![CMD_ERASE_CHIP.cmd]);
+    }
+
     #[test]
     fn test_erase() {
         let (port, ()) = CMD_ERASE_64K.test_ok(vec![38], &[42]);
@@ -230,6 +293,26 @@ mod tests {
         assert_eq!(written[4..], content[..PAGE_SIZE]);
     }
 
+    #[test]
+    fn test_address_range() {
+        let read_data = ReadData { addr: 0xff_ff00 };
+        let (port, _) = CMD_READ_PAGE.test_ok(vec![0; PAGE_SIZE], &read_data);
+        assert_eq!(port.written(), vec![CMD_READ_PAGE.cmd, 0xff, 0xff, 0]);
+        let prog_data = ProgData {
+            addr: ADDRESS_SPACE,
+            data: &[0],
+        };
+        let port = CMD_PROGRAM_PAGE.test_err(vec![0; 4], &prog_data);
+        assert!(port.written().is_empty());
+    }
+
+    #[test]
+    fn test_get_cdone() {
+        let (port, reply) = CMD_GET_CDONE.test_ok(vec![1], &());
+        assert_eq!(port.written(), vec![CMD_GET_CDONE.cmd]);
+        assert_eq!(reply, [1]);
+    }
+
     #[test]
     fn test_release() {
         let (port, ()) = CMD_RELEASE_FPGA.test_ok(vec![0], &());
@@ -1,81 +1,219 @@
 use std::io::Write;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
 use tracing::{info, instrument};
 
 use crate::cmds::{self, PAGE_SIZE};
 use crate::err::Error;
+use crate::flash::{FlashGeometry, FlashId};
 use crate::serialport::SerialPort;
 
+/// An iceFUN board, reached through its firmware's command protocol.
 pub struct Device {
-    pub port: Box<dyn SerialPort>,
+    port: Box<dyn SerialPort>,
 }
 
+const CDONE_POLL_PERIOD: Duration = Duration::from_millis(10);
+
 impl Device {
+    /// Talk to the firmware over `transport`, usually the port from [`crate::open_board`].
+    /// Any `Read + Write` type which owns its data (`'static`) can be used.
+    pub fn new(transport: impl SerialPort + 'static) -> Self {
+        Self {
+            port: Box::new(transport),
+        }
+    }
+
     /// # Errors
     ///
     /// Will return `Err` if commnication fails.
     #[instrument(skip(self))]
     fn getver(&mut self) -> Result<cmds::GetVerReply, Error> {
-        cmds::CMD_GET_VER.run(&mut self.port)
+        cmds::CMD_GET_VER.run(self.port.as_mut())
+    }
+
+    /// Read the firmware version.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if commnication fails.
+    pub fn version(&mut self) -> Result<u8, Error> {
+        Ok(self.getver()?.0)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if commnication fails.
+    pub fn reset_fpga(mut self) -> Result<(FlashId, DeviceInReset), Error> {
+        let flash_id = FlashId::from(cmds::CMD_RESET.run(self.port.as_mut())?);
+        let dev_in_reset = DeviceInReset {
+            dev: Some(self),
+            geometry: flash_id.geometry(),
+        };
+        Ok((flash_id, dev_in_reset))
+    }
+
+    /// Read the FPGA CDONE pin, which is high once the FPGA has configured.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if commnication fails.
+    #[instrument(skip(self))]
+    pub fn cdone(&mut self) -> Result<bool, Error> {
+        let reply = cmds::CMD_GET_CDONE.run(self.port.as_mut())?;
+        Ok(reply[0] != 0)
     }
 
+    /// Poll CDONE until it goes high or `timeout` expires.
+    /// Returns the last CDONE state read.
+    ///
     /// # Errors
     ///
     /// Will return `Err` if commnication fails.
-    pub fn reset_fpga(mut self) -> Result<([u8; 3], DeviceInReset), Error> {
-        let ver = cmds::CMD_RESET.run(&mut self.port)?;
-        Ok((ver, DeviceInReset(self)))
+    #[instrument(skip(self))]
+    pub fn wait_cdone(&mut self, timeout: Duration) -> Result<bool, Error> {
+        let start = Instant::now();
+        loop {
+            let cdone = self.cdone()?;
+            if cdone || start.elapsed() >= timeout {
+                info!(cdone, "CDONE");
+                return Ok(cdone);
+            }
+            sleep(CDONE_POLL_PERIOD);
+        }
     }
 
     /// # Errors
     ///
     /// Will return `Err` if commnication fails.
     #[instrument(skip(self))]
-    pub fn prepare(mut self) -> Result<DeviceInReset, Error> {
+    pub fn prepare(mut self) -> Result<(FlashId, DeviceInReset), Error> {
         let ver = self.getver()?;
-        info!(%ver, "iceFUN version");
-        let (reset_reply, dev_in_reset) = self.reset_fpga()?;
-        info!(?reset_reply, "Flash ID");
-        Ok(dev_in_reset)
+        let (flash_id, dev_in_reset) = self.reset_fpga()?;
+        info!(
+            %ver,
+            %flash_id,
+            capacity = dev_in_reset.geometry.capacity,
+            page_size = dev_in_reset.geometry.page_size,
+            block_size = dev_in_reset.geometry.block_size,
+            "iceFUN device"
+        );
+        Ok((flash_id, dev_in_reset))
     }
 }
 
-pub trait Programmable {
+/// A flash chip whose layout is known.
+pub trait FlashDevice {
+    fn geometry(&self) -> &FlashGeometry;
+}
+
+/// A flash chip which can be erased and written one page at a time.
+pub trait Programmable: FlashDevice {
+    /// Erase the whole chip.
+    fn erase_chip(&mut self) -> Result<(), Error>;
+    /// Erase the 64KiB block with index `page`.
     fn erase64k(&mut self, page: u8) -> Result<(), Error>;
+    /// Program the page at `addr`, padding `data` with zeros to a whole page.
     fn program_page(&mut self, addr: usize, data: &[u8]) -> Result<(), Error>;
+    /// Check the page at `addr` against `data`, padded as by `program_page`.
     fn verify_page(&mut self, addr: usize, data: &[u8]) -> Result<(), Error>;
 }
 
-pub trait Dumpable {
+/// A flash chip which can be read one page at a time.
+pub trait Dumpable: FlashDevice {
+    /// Write the first `len` bytes of the page at `addr` to `output`.
     fn read_page(&mut self, addr: usize, len: usize, output: &mut impl Write) -> Result<(), Error>;
 }
 
-pub struct DeviceInReset(pub Device);
+/// A [`Device`] with the FPGA held in reset, so the flash can be accessed.
+/// The FPGA is released when this is dropped, or by [`DeviceInReset::release`].
+pub struct DeviceInReset {
+    dev: Option<Device>,
+    geometry: FlashGeometry,
+}
+
+impl DeviceInReset {
+    /// # Errors
+    ///
+    /// Will return `Err` if `len` bytes at `addr` are beyond the flash.
+    fn check_range(&self, addr: usize, len: usize) -> Result<(), Error> {
+        if addr
+            .checked_add(len)
+            .map_or(true, |end| end > self.geometry.capacity)
+        {
+            return Err(Error::Range(format!(
+                "{len} bytes at {addr:#x} overruns {} byte flash",
+                self.geometry.capacity
+            )));
+        }
+        Ok(())
+    }
+
+    fn port(&mut self) -> &mut dyn SerialPort {
+        self.dev
+            .as_mut()
+            .expect("device is only taken by release")
+            .port
+            .as_mut()
+    }
+
+    /// Release the FPGA from reset, returning the [`Device`] so that
+    /// the result of configuration can be checked with [`Device::cdone`].
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if commnication fails.
+    #[instrument(skip(self))]
+    pub fn release(mut self) -> Result<Device, Error> {
+        let mut dev = self.dev.take().expect("device is only taken by release");
+        cmds::CMD_RELEASE_FPGA.run(dev.port.as_mut())?;
+        Ok(dev)
+    }
+}
+
+impl FlashDevice for DeviceInReset {
+    fn geometry(&self) -> &FlashGeometry {
+        &self.geometry
+    }
+}
 
 impl Programmable for DeviceInReset {
     /// # Errors
     ///
     /// Will return `Err` if commnication fails.
     #[instrument(skip(self))]
+    fn erase_chip(&mut self) -> Result<(), Error> {
+        cmds::CMD_ERASE_CHIP.run(self.port())
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if commnication fails, or the block is beyond the flash.
+    #[instrument(skip(self))]
     fn erase64k(&mut self, page: u8) -> Result<(), Error> {
-        cmds::CMD_ERASE_64K.run_args(&mut self.0.port, &[page])
+        let block_size = self.geometry.block_size;
+        self.check_range(usize::from(page) * block_size, block_size)?;
+        cmds::CMD_ERASE_64K.run_args(self.port(), &[page])
     }
 
     /// # Errors
     ///
-    /// Will return `Err` if commnication fails.
+    /// Will return `Err` if commnication fails, or the page is beyond the flash.
     #[instrument(skip(self, data))]
     fn program_page(&mut self, addr: usize, data: &[u8]) -> Result<(), Error> {
-        cmds::CMD_PROGRAM_PAGE.run_args(&mut self.0.port, &cmds::ProgData { addr, data })?;
+        self.check_range(addr, data.len().min(PAGE_SIZE))?;
+        cmds::CMD_PROGRAM_PAGE.run_args(self.port(), &cmds::ProgData { addr, data })?;
         Ok(())
     }
 
     /// # Errors
     ///
-    /// Will return `Err` if commnication fails.
+    /// Will return `Err` if commnication fails, or the page is beyond the flash.
     #[instrument(skip(self, data))]
     fn verify_page(&mut self, addr: usize, data: &[u8]) -> Result<(), Error> {
-        cmds::CMD_VERIFY_PAGE.run_args(&mut self.0.port, &cmds::ProgData { addr, data })?;
+        self.check_range(addr, data.len().min(PAGE_SIZE))?;
+        cmds::CMD_VERIFY_PAGE.run_args(self.port(), &cmds::ProgData { addr, data })?;
         Ok(())
     }
 }
@@ -91,10 +229,13 @@ impl Dumpable for DeviceInReset {
                 "Reading {len} bytes of {PAGE_SIZE} byte page"
             )));
         }
-        if addr + len > (1024 * 1024) {
-            return Err(Error::Dump("Reading beyond 1MB".to_string()));
+        if addr + len > self.geometry.capacity {
+            return Err(Error::Dump(format!(
+                "Reading beyond {} byte flash",
+                self.geometry.capacity
+            )));
         }
-        let data = cmds::CMD_READ_PAGE.run_args(&mut self.0.port, &cmds::ReadData { addr })?;
+        let data = cmds::CMD_READ_PAGE.run_args(self.port(), &cmds::ReadData { addr })?;
         output.write_all(&data.0[..len])?;
         Ok(())
     }
@@ -102,6 +243,37 @@ impl Dumpable for DeviceInReset {
 
 impl Drop for DeviceInReset {
     fn drop(&mut self) {
-        cmds::CMD_RELEASE_FPGA.run(&mut self.0.port).ok();
+        if let Some(dev) = &mut self.dev {
+            cmds::CMD_RELEASE_FPGA.run(dev.port.as_mut()).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_mocks::MockPort;
+
+    #[test]
+    fn test_device() {
+        let port = MockPort::new(vec![38, 3, 0xef, 0x40, 0x16, 0]);
+        let mut dev = Device::new(port.clone());
+        assert_eq!(dev.version().unwrap(), 3);
+        let (flash_id, fpga) = dev.reset_fpga().unwrap();
+        assert_eq!(flash_id.geometry().capacity, 0x40_0000);
+        assert_eq!(fpga.geometry(), &flash_id.geometry());
+        drop(fpga.release().unwrap());
+        assert_eq!(port.written(), vec![0xb1, 0xb2, 0xb9]);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let port = MockPort::new(vec![0xef, 0x40, 0x14, 0]);
+        let (_, mut fpga) = Device::new(port.clone()).reset_fpga().unwrap();
+        assert!(fpga.program_page(0x10_0000, &[0; 16]).is_err());
+        assert!(fpga.verify_page(0xf_fff0, &[0; 32]).is_err());
+        assert!(fpga.erase64k(16).is_err());
+        drop(fpga.release().unwrap());
+        assert_eq!(port.written(), vec![0xb2, 0xb9]);
     }
 }
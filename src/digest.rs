@@ -0,0 +1,91 @@
+use std::fmt::{Display, Write as _};
+use std::io::{self, Read};
+
+use sha2::{Digest as _, Sha256};
+
+/// SHA-256 and CRC32 of the bytes programmed or dumped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Digest {
+    pub sha256: [u8; 32],
+    pub crc32: u32,
+}
+
+impl Digest {
+    /// Digest of everything `reader` returns.
+    pub fn from_reader(mut reader: impl Read) -> io::Result<Self> {
+        let mut hasher = Hasher::default();
+        let mut buf = [0u8; 4096];
+        loop {
+            let len = reader.read(&mut buf)?;
+            if len == 0 {
+                return Ok(hasher.finish());
+            }
+            hasher.update(&buf[..len]);
+        }
+    }
+
+    /// The SHA-256 in lower case hex.
+    #[must_use]
+    pub fn sha256_hex(&self) -> String {
+        self.sha256.iter().fold(String::new(), |mut hex, b| {
+            write!(hex, "{b:02x}").expect("writing to String");
+            hex
+        })
+    }
+
+    /// Compare the SHA-256 with a hex string, ignoring case.
+    #[must_use]
+    pub fn matches_sha256(&self, expected: &str) -> bool {
+        self.sha256_hex().eq_ignore_ascii_case(expected.trim())
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sha256 {} crc32 {:08x}", self.sha256_hex(), self.crc32)
+    }
+}
+
+/// Computes a [`Digest`] as pages stream through.
+#[derive(Clone, Default)]
+pub(crate) struct Hasher {
+    sha256: Sha256,
+    crc32: crc32fast::Hasher,
+}
+
+impl Hasher {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        self.crc32.update(data);
+    }
+
+    pub(crate) fn finish(self) -> Digest {
+        Digest {
+            sha256: self.sha256.finalize().into(),
+            crc32: self.crc32.finalize(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest() {
+        let mut hasher = Hasher::default();
+        hasher.update(b"a");
+        hasher.update(b"bc");
+        let digest = hasher.finish();
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(digest.sha256_hex(), sha256);
+        assert_eq!(digest.crc32, 0x3524_41c2);
+        assert!(digest.matches_sha256(&sha256.to_uppercase()));
+        assert!(!digest.matches_sha256("ba78"));
+        assert_eq!(Digest::from_reader(&b"abc"[..]).unwrap(), digest);
+        assert_eq!(
+            digest.to_string(),
+            format!("sha256 {sha256} crc32 352441c2")
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// Output format for flash dumps.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Raw bytes
+    #[default]
+    Raw,
+    /// Intel HEX records
+    Ihex,
+    /// Canonical hexdump, like `xxd`
+    Hexdump,
+    /// C array, like `xxd -i`
+    C,
+}
+
+const C_ARRAY_NAME: &str = "flash";
+/// Address and eight groups of two bytes in hex
+const HEXDUMP_WIDTH: usize = 9 + 8 * 5;
+
+/// Encodes bytes written to it in a [`DumpFormat`], labelled with flash addresses.
+/// [`Formatter::finish`] must be called to write any trailer.
+pub struct Formatter<W: Write> {
+    writer: W,
+    format: DumpFormat,
+    /// Address of the first byte in `line`
+    addr: usize,
+    line: Vec<u8>,
+    /// Upper 16 bits of the address in the last Intel HEX extended linear address record
+    upper: Option<usize>,
+    len: usize,
+}
+
+impl<W: Write> Formatter<W> {
+    /// # Errors
+    ///
+    /// Will return `Err` if writing the header fails.
+    pub fn new(mut writer: W, format: DumpFormat, addr: usize) -> io::Result<Self> {
+        if format == DumpFormat::C {
+            writeln!(writer, "unsigned char {C_ARRAY_NAME}[] = {{")?;
+        }
+        Ok(Self {
+            writer,
+            format,
+            addr,
+            line: vec![],
+            upper: None,
+            len: 0,
+        })
+    }
+
+    fn line_len(&self) -> usize {
+        match self.format {
+            DumpFormat::Raw => 1,
+            // Records must not cross a 64K boundary
+            DumpFormat::Ihex => 16.min(0x1_0000 - (self.addr & 0xffff)),
+            DumpFormat::Hexdump => 16,
+            DumpFormat::C => 12,
+        }
+    }
+
+    fn ihex_record(&mut self, addr: usize, record_type: u8, data: &[u8]) -> io::Result<()> {
+        let mut record = vec![u8::try_from(data.len()).expect("record length checked")];
+        record.extend(u16::try_from(addr & 0xffff).expect("masked").to_be_bytes());
+        record.push(record_type);
+        record.extend(data);
+        record.push(0u8.wrapping_sub(record.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))));
+        let hex = record.iter().fold(String::new(), |mut hex, b| {
+            write!(hex, "{b:02X}").expect("writing to String");
+            hex
+        });
+        writeln!(self.writer, ":{hex}")
+    }
+
+    fn emit_line(&mut self) -> io::Result<()> {
+        let line = std::mem::take(&mut self.line);
+        match self.format {
+            DumpFormat::Raw => self.writer.write_all(&line)?,
+            DumpFormat::Ihex => {
+                let upper = self.addr >> 16;
+                if self.upper != Some(upper) {
+                    let upper_bytes = u16::try_from(upper)
+                        .map_err(|_| io::Error::other("address beyond 32 bits"))?
+                        .to_be_bytes();
+                    self.ihex_record(0, 0x04, &upper_bytes)?;
+                    self.upper = Some(upper);
+                }
+                self.ihex_record(self.addr, 0x00, &line)?;
+            }
+            DumpFormat::Hexdump => {
+                let mut text = format!("{:08x}:", self.addr);
+                for (i, byte) in line.iter().enumerate() {
+                    let sep = if i % 2 == 0 { " " } else { "" };
+                    write!(text, "{sep}{byte:02x}").expect("writing to String");
+                }
+                let ascii: String = line
+                    .iter()
+                    .map(|&b| {
+                        if b == b' ' || b.is_ascii_graphic() {
+                            char::from(b)
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                writeln!(self.writer, "{text:HEXDUMP_WIDTH$}  {ascii}")?;
+            }
+            DumpFormat::C => {
+                let bytes: Vec<String> = line.iter().map(|b| format!("0x{b:02x}")).collect();
+                writeln!(self.writer, "  {},", bytes.join(", "))?;
+            }
+        }
+        self.addr += line.len();
+        Ok(())
+    }
+
+    /// Write any partial line and trailer, returning the inner writer.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if writing fails.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.line.is_empty() {
+            self.emit_line()?;
+        }
+        match self.format {
+            DumpFormat::Ihex => writeln!(self.writer, ":00000001FF")?,
+            DumpFormat::C => {
+                writeln!(self.writer, "}};")?;
+                writeln!(
+                    self.writer,
+                    "unsigned int {C_ARRAY_NAME}_len = {};",
+                    self.len
+                )?;
+            }
+            DumpFormat::Raw | DumpFormat::Hexdump => {}
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for Formatter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.format == DumpFormat::Raw {
+            self.len += buf.len();
+            return self.writer.write(buf);
+        }
+        for &byte in buf {
+            self.line.push(byte);
+            if self.line.len() == self.line_len() {
+                self.emit_line()?;
+            }
+        }
+        self.len += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(format: DumpFormat, addr: usize, data: &[u8]) -> String {
+        let mut formatter = Formatter::new(vec![], format, addr).unwrap();
+        formatter.write_all(data).unwrap();
+        String::from_utf8(formatter.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_raw() {
+        assert_eq!(format(DumpFormat::Raw, 0x100, b"abc"), "abc");
+    }
+
+    #[test]
+    fn test_ihex() {
+        let data: Vec<u8> = (0..20).collect();
+        assert_eq!(
+            format(DumpFormat::Ihex, 0x1_fff8, &data),
+            "\
+:020000040001F9
+:08FFF8000001020304050607E5
+:020000040002F8
+:0C00000008090A0B0C0D0E0F1011121352
+:00000001FF
+"
+        );
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let data: Vec<u8> = (0x3c..0x4f).collect();
+        assert_eq!(
+            format(DumpFormat::Hexdump, 0x100, &data),
+            "\
+00000100: 3c3d 3e3f 4041 4243 4445 4647 4849 4a4b  <=>?@ABCDEFGHIJK
+00000110: 4c4d 4e                                  LMN
+"
+        );
+    }
+
+    #[test]
+    fn test_c() {
+        assert_eq!(
+            format(DumpFormat::C, 0, &[0, 1, 0xff]),
+            "\
+unsigned char flash[] = {
+  0x00, 0x01, 0xff,
+};
+unsigned int flash_len = 3;
+"
+        );
+    }
+}
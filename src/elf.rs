@@ -0,0 +1,210 @@
+use tracing::{info, instrument};
+
+use crate::err::Error;
+use crate::regions::Regions;
+
+const MAGIC: &[u8] = b"\x7fELF";
+const CLASS_32: u8 = 1;
+const CLASS_64: u8 = 2;
+const DATA_LSB: u8 = 1;
+const DATA_MSB: u8 = 2;
+const PT_LOAD: usize = 1;
+
+/// Field offsets and sizes which differ between 32 and 64 bit ELF files.
+struct Layout {
+    phoff: (usize, usize),
+    phentsize: usize,
+    phnum: usize,
+    p_offset: (usize, usize),
+    p_paddr: (usize, usize),
+    p_filesz: (usize, usize),
+}
+
+const LAYOUT_32: Layout = Layout {
+    phoff: (0x1c, 4),
+    phentsize: 0x2a,
+    phnum: 0x2c,
+    p_offset: (0x04, 4),
+    p_paddr: (0x0c, 4),
+    p_filesz: (0x10, 4),
+};
+
+const LAYOUT_64: Layout = Layout {
+    phoff: (0x20, 8),
+    phentsize: 0x36,
+    phnum: 0x38,
+    p_offset: (0x08, 8),
+    p_paddr: (0x18, 8),
+    p_filesz: (0x20, 8),
+};
+
+struct Elf<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+fn elf_err(msg: impl Into<String>) -> Error {
+    Error::Parse(format!("ELF {}", msg.into()))
+}
+
+impl Elf<'_> {
+    fn uint(&self, offset: usize, (field, size): (usize, usize)) -> Result<usize, Error> {
+        let truncated = || elf_err(format!("truncated at {offset:#x} + {field:#x}"));
+        let bytes = offset
+            .checked_add(field)
+            .and_then(|start| self.data.get(start..start.checked_add(size)?))
+            .ok_or_else(truncated)?;
+        let value = if self.big_endian {
+            bytes.iter().fold(0u64, |v, &b| (v << 8) | u64::from(b))
+        } else {
+            bytes
+                .iter()
+                .rev()
+                .fold(0u64, |v, &b| (v << 8) | u64::from(b))
+        };
+        Ok(usize::try_from(value)?)
+    }
+}
+
+/// Load the `PT_LOAD` segments of an ELF file into regions at their physical addresses.
+///
+/// # Errors
+///
+/// Will return `Err` if the file is not a valid ELF file, or segments overlap.
+#[instrument(skip(data))]
+pub fn parse(data: &[u8]) -> Result<Regions, Error> {
+    if data.get(..MAGIC.len()) != Some(MAGIC) || data.len() < 6 {
+        return Err(elf_err("magic not found"));
+    }
+    let layout = match data[4] {
+        CLASS_32 => &LAYOUT_32,
+        CLASS_64 => &LAYOUT_64,
+        class => return Err(elf_err(format!("class {class} not supported"))),
+    };
+    let big_endian = match data[5] {
+        DATA_LSB => false,
+        DATA_MSB => true,
+        encoding => return Err(elf_err(format!("data encoding {encoding} not supported"))),
+    };
+    let elf = Elf { data, big_endian };
+    let phoff = elf.uint(0, layout.phoff)?;
+    let phentsize = elf.uint(0, (layout.phentsize, 2))?;
+    let phnum = elf.uint(0, (layout.phnum, 2))?;
+
+    let mut regions = Regions::new();
+    for index in 0..phnum {
+        let header = index
+            .checked_mul(phentsize)
+            .and_then(|header| header.checked_add(phoff))
+            .ok_or_else(|| elf_err(format!("program header {index} out of range")))?;
+        if elf.uint(header, (0, 4))? != PT_LOAD {
+            continue;
+        }
+        let offset = elf.uint(header, layout.p_offset)?;
+        let paddr = elf.uint(header, layout.p_paddr)?;
+        let filesz = elf.uint(header, layout.p_filesz)?;
+        let segment = offset
+            .checked_add(filesz)
+            .and_then(|end| data.get(offset..end))
+            .ok_or_else(|| elf_err(format!("segment {index} beyond end of file")))?;
+        if paddr.checked_add(filesz).is_none() {
+            return Err(elf_err(format!("segment {index} address overflows")));
+        }
+        info!(index, paddr, filesz, "Segment");
+        regions.add(paddr, segment.to_vec())?;
+    }
+    Ok(regions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a 32 bit little endian ELF file with the given segments.
+    fn build(segments: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let phoff = 0x34u32;
+        let mut data = vec![0u8; 0x34];
+        data[..4].copy_from_slice(MAGIC);
+        data[4] = CLASS_32;
+        data[5] = DATA_LSB;
+        data[0x1c..0x20].copy_from_slice(&phoff.to_le_bytes());
+        data[0x2a..0x2c].copy_from_slice(&0x20u16.to_le_bytes());
+        let phnum = u16::try_from(segments.len()).unwrap();
+        data[0x2c..0x2e].copy_from_slice(&phnum.to_le_bytes());
+        let mut file_offset = phoff + 0x20 * u32::from(phnum);
+        let mut contents: Vec<u8> = vec![];
+        for (p_type, paddr, segment) in segments {
+            let filesz = u32::try_from(segment.len()).unwrap();
+            let mut header = [0u8; 0x20];
+            header[..4].copy_from_slice(&p_type.to_le_bytes());
+            header[4..8].copy_from_slice(&file_offset.to_le_bytes());
+            header[8..12].copy_from_slice(&(paddr + 0x1000).to_le_bytes());
+            header[12..16].copy_from_slice(&paddr.to_le_bytes());
+            header[16..20].copy_from_slice(&filesz.to_le_bytes());
+            data.extend(header);
+            contents.extend(*segment);
+            file_offset += filesz;
+        }
+        data.extend(contents);
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let data = build(&[
+            (1, 0x2000_0000, &[1, 2, 3]),
+            (4, 0, &[9]),
+            (1, 0x2000_0100, &[4]),
+        ]);
+        let regions = parse(&data).unwrap();
+        let regions = regions
+            .regions()
+            .iter()
+            .map(|r| (r.offset, r.data.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            regions,
+            vec![(0x2000_0000, vec![1, 2, 3]), (0x2000_0100, vec![4])]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse(b"not an elf").is_err());
+        let data = build(&[(1, 0, &[1, 2, 3])]);
+        assert!(parse(&data[..data.len() - 1]).is_err());
+        assert!(parse(&build(&[(1, 0, &[1, 2]), (1, 1, &[3])])).is_err());
+        assert!(parse(&data[..0x40]).is_err());
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut data = vec![0u8; 0x40];
+        data[..4].copy_from_slice(MAGIC);
+        data[4] = CLASS_64;
+        data[5] = DATA_LSB;
+        data[0x20..0x28].copy_from_slice(&u64::MAX.to_le_bytes());
+        data[0x36..0x38].copy_from_slice(&0x38u16.to_le_bytes());
+        data[0x38..0x3a].copy_from_slice(&2u16.to_le_bytes());
+        assert!(parse(&data).is_err());
+
+        let mut data = vec![0u8; 0x78];
+        data[..4].copy_from_slice(MAGIC);
+        data[4] = CLASS_64;
+        data[5] = DATA_LSB;
+        data[0x20..0x28].copy_from_slice(&0x40u64.to_le_bytes());
+        data[0x36..0x38].copy_from_slice(&0x38u16.to_le_bytes());
+        data[0x38..0x3a].copy_from_slice(&1u16.to_le_bytes());
+        data[0x40..0x44].copy_from_slice(&1u32.to_le_bytes());
+        data[0x58..0x60].copy_from_slice(&(u64::MAX - 1).to_le_bytes());
+        data[0x60..0x68].copy_from_slice(&4u64.to_le_bytes());
+        assert!(parse(&data).is_err());
+        data[0x58..0x60].copy_from_slice(&0x100u64.to_le_bytes());
+        assert!(parse(&data).is_ok());
+
+        let mut data = build(&[(1, 0, &[1])]);
+        data[0x38..0x3c].copy_from_slice(&u32::MAX.to_le_bytes());
+        data[0x44..0x48].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(parse(&data).is_err());
+    }
+}
@@ -1,9 +1,16 @@
+/// Errors from the library. Those with a message describe what failed.
 #[derive(Debug)]
 pub enum Error {
     FromInt(std::num::TryFromIntError),
     Io(std::io::Error),
     Cmd(String),
     Dump(String),
+    Range(String),
+    Bitstream(String),
+    Multiboot(String),
+    Manifest(String),
+    Parse(String),
+    Cancelled(String),
 }
 
 impl std::fmt::Display for Error {
@@ -13,6 +20,12 @@ impl std::fmt::Display for Error {
             Self::Io(err) => err.fmt(f),
             Self::Cmd(msg) => write!(f, "Cmd Error {msg}"),
             Self::Dump(msg) => write!(f, "Dump Error {msg}"),
+            Self::Range(msg) => write!(f, "Range Error {msg}"),
+            Self::Bitstream(msg) => write!(f, "Bitstream Error {msg}"),
+            Self::Multiboot(msg) => write!(f, "Multiboot Error {msg}"),
+            Self::Manifest(msg) => write!(f, "Manifest Error {msg}"),
+            Self::Parse(msg) => write!(f, "Parse Error {msg}"),
+            Self::Cancelled(msg) => write!(f, "Cancelled {msg}"),
         }
     }
 }
@@ -0,0 +1,206 @@
+use std::fmt::Display;
+
+use tracing::warn;
+
+use crate::cmds::{ADDRESS_SPACE, ERASE_BLOCK_SIZE, PAGE_SIZE};
+
+/// JEDEC identity of the SPI flash, as returned when the FPGA is reset.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FlashId {
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    pub capacity: u8,
+}
+
+impl From<[u8; 3]> for FlashId {
+    fn from(value: [u8; 3]) -> Self {
+        Self {
+            manufacturer: value[0],
+            memory_type: value[1],
+            capacity: value[2],
+        }
+    }
+}
+
+impl FlashId {
+    /// Look up this identity in the table of known flash chips.
+    #[must_use]
+    pub fn known(&self) -> Option<&'static KnownFlash> {
+        KNOWN_FLASH.iter().find(|known| known.id == *self)
+    }
+
+    /// Size of the flash chip in bytes, if it can be determined.
+    /// This may be more than [`FlashId::geometry`] can address.
+    /// Unknown chips fall back to the common convention that the
+    /// capacity byte is the log2 of the size.
+    #[must_use]
+    pub fn size(&self) -> Option<usize> {
+        if let Some(known) = self.known() {
+            Some(known.size)
+        } else if (0x10..0x20).contains(&self.capacity) {
+            Some(1 << self.capacity)
+        } else {
+            None
+        }
+    }
+
+    /// Geometry of this flash. Unknown chips of undetermined size
+    /// are assumed to have the default geometry. The capacity is limited
+    /// to the 16 MiB which 24 bit addresses reach.
+    #[must_use]
+    pub fn geometry(&self) -> FlashGeometry {
+        let default = FlashGeometry::default();
+        let mut capacity = self.size().unwrap_or(default.capacity);
+        if capacity > ADDRESS_SPACE {
+            warn!(
+                size = capacity,
+                "Only the first {ADDRESS_SPACE} bytes of flash can be reached"
+            );
+            capacity = ADDRESS_SPACE;
+        }
+        FlashGeometry {
+            sector_size: self.known().map_or(default.sector_size, |k| k.sector_size),
+            capacity,
+            ..default
+        }
+    }
+}
+
+impl Display for FlashId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02x}{:02x}{:02x}",
+            self.manufacturer, self.memory_type, self.capacity
+        )?;
+        if let Some(known) = self.known() {
+            write!(f, " ({} {})", known.vendor, known.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Layout of the flash, used for bounds checks and erase planning.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FlashGeometry {
+    /// Programming page size in bytes
+    pub page_size: usize,
+    /// Smallest erasable sector in bytes
+    pub sector_size: usize,
+    /// Erase block size in bytes, as erased by `Programmable::erase64k`
+    pub block_size: usize,
+    /// Total capacity in bytes
+    pub capacity: usize,
+}
+
+impl Default for FlashGeometry {
+    fn default() -> Self {
+        Self {
+            page_size: PAGE_SIZE,
+            sector_size: 4 * K,
+            block_size: ERASE_BLOCK_SIZE,
+            capacity: M,
+        }
+    }
+}
+
+/// A flash chip with known geometry.
+#[derive(Debug)]
+pub struct KnownFlash {
+    pub id: FlashId,
+    pub vendor: &'static str,
+    pub name: &'static str,
+    /// Size in bytes
+    pub size: usize,
+    /// Smallest erasable sector in bytes
+    pub sector_size: usize,
+}
+
+const fn known(
+    id: [u8; 3],
+    vendor: &'static str,
+    name: &'static str,
+    size: usize,
+    sector_size: usize,
+) -> KnownFlash {
+    KnownFlash {
+        id: FlashId {
+            manufacturer: id[0],
+            memory_type: id[1],
+            capacity: id[2],
+        },
+        vendor,
+        name,
+        size,
+        sector_size,
+    }
+}
+
+const K: usize = 1024;
+const M: usize = 1024 * 1024;
+
+/// SPI flash chips with known geometry.
+pub const KNOWN_FLASH: &[KnownFlash] = &[
+    known([0x01, 0x40, 0x15], "Spansion", "S25FL116K", 2 * M, 4 * K),
+    known([0x01, 0x40, 0x16], "Spansion", "S25FL132K", 4 * M, 4 * K),
+    known([0x1f, 0x84, 0x01], "Adesto", "AT25SF041", M / 2, 4 * K),
+    known([0x1f, 0x85, 0x01], "Adesto", "AT25SF081", M, 4 * K),
+    known([0x1f, 0x86, 0x01], "Adesto", "AT25SF161", 2 * M, 4 * K),
+    known([0x20, 0x20, 0x14], "Micron", "M25P80", M, 64 * K),
+    known([0x20, 0x20, 0x15], "Micron", "M25P16", 2 * M, 64 * K),
+    known([0x20, 0xba, 0x16], "Micron", "N25Q032", 4 * M, 4 * K),
+    known([0x9d, 0x60, 0x14], "ISSI", "IS25LP080", M, 4 * K),
+    known([0x9d, 0x60, 0x15], "ISSI", "IS25LP016", 2 * M, 4 * K),
+    known([0xc2, 0x20, 0x14], "Macronix", "MX25L8006E", M, 4 * K),
+    known([0xc2, 0x20, 0x15], "Macronix", "MX25L1606E", 2 * M, 4 * K),
+    known([0xc2, 0x20, 0x16], "Macronix", "MX25L3233F", 4 * M, 4 * K),
+    known([0xc8, 0x40, 0x14], "GigaDevice", "GD25Q80", M, 4 * K),
+    known([0xc8, 0x40, 0x15], "GigaDevice", "GD25Q16", 2 * M, 4 * K),
+    known([0xef, 0x40, 0x14], "Winbond", "W25Q80", M, 4 * K),
+    known([0xef, 0x40, 0x15], "Winbond", "W25Q16", 2 * M, 4 * K),
+    known([0xef, 0x40, 0x16], "Winbond", "W25Q32", 4 * M, 4 * K),
+    known([0xef, 0x40, 0x17], "Winbond", "W25Q64", 8 * M, 4 * K),
+    known([0xef, 0x40, 0x18], "Winbond", "W25Q128", 16 * M, 4 * K),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known() {
+        let id = FlashId::from([0xef, 0x40, 0x14]);
+        let known = id.known().unwrap();
+        assert_eq!(known.name, "W25Q80");
+        assert_eq!(id.size(), Some(M));
+        assert_eq!(id.to_string(), "ef4014 (Winbond W25Q80)");
+        assert_eq!(
+            FlashId::from([0xef, 0x40, 0x18]).geometry().capacity,
+            16 * M
+        );
+    }
+
+    #[test]
+    fn test_unknown() {
+        let id = FlashId::from([0x42, 0x42, 0x15]);
+        assert!(id.known().is_none());
+        assert_eq!(id.size(), Some(2 * M));
+        assert_eq!(FlashId::from([0x42, 0x42, 0x01]).size(), None);
+        assert_eq!(id.to_string(), "424215");
+        assert_eq!(
+            FlashId::from([0x42, 0x42, 0x01]).geometry(),
+            FlashGeometry::default()
+        );
+    }
+
+    #[test]
+    fn test_address_space() {
+        let id = FlashId::from([0xef, 0x40, 0x19]);
+        assert_eq!(id.size(), Some(32 * M));
+        assert_eq!(id.geometry().capacity, 16 * M);
+        assert_eq!(
+            FlashId::from([0x42, 0x42, 0x1f]).geometry().capacity,
+            16 * M
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use tracing::instrument;
+
+use crate::err::Error;
+use crate::image::decode_hex;
+use crate::regions::{RegionBuilder, Regions};
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Parse Intel HEX records into regions.
+///
+/// # Errors
+///
+/// Will return `Err` if a record is malformed, or records overlap.
+#[instrument(skip(text))]
+pub fn parse(text: &str) -> Result<Regions, Error> {
+    let mut builder = RegionBuilder::default();
+    let mut base = 0usize;
+    for (line_no, line) in text.lines().enumerate() {
+        let err = |msg: &str| Error::Parse(format!("line {}: {msg}", line_no + 1));
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = line
+            .strip_prefix(':')
+            .and_then(decode_hex)
+            .ok_or_else(|| err("invalid record"))?;
+        if record.len() < 5 || record.len() != 5 + usize::from(record[0]) {
+            return Err(err("invalid length"));
+        }
+        if record.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return Err(err("invalid checksum"));
+        }
+        let addr = usize::from(u16::from_be_bytes([record[1], record[2]]));
+        let data = &record[4..record.len() - 1];
+        match record[3] {
+            DATA => builder.push(base + addr, data)?,
+            END_OF_FILE => break,
+            EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS => {
+                let [hi, lo] = data else {
+                    return Err(err("invalid address"));
+                };
+                let shift = if record[3] == EXTENDED_SEGMENT_ADDRESS {
+                    4
+                } else {
+                    16
+                };
+                base = usize::from(u16::from_be_bytes([*hi, *lo])) << shift;
+            }
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => {}
+            record_type => return Err(err(&format!("unknown record type {record_type:#x}"))),
+        }
+    }
+    builder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "\
+:0400000001020304F2
+:020000040001F9
+:02000000AABB99
+:02000200CCDD53
+:0400000500000000F7
+:00000001FF
+";
+        let regions = parse(text).unwrap();
+        let regions = regions
+            .regions()
+            .iter()
+            .map(|r| (r.offset, r.data.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            regions,
+            vec![
+                (0, vec![1, 2, 3, 4]),
+                (0x10000, vec![0xaa, 0xbb, 0xcc, 0xdd]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse("0400000001020304F2").is_err());
+        assert!(parse(":0400000001020304F3").is_err());
+        assert!(parse(":0500000001020304F2").is_err());
+        assert!(parse(":0100000001FE\n:0100000001FE").is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::Path;
+
+use tracing::instrument;
+
+use crate::err::Error;
+use crate::regions::Regions;
+use crate::{elf, ihex, srec};
+
+/// Format of a file to program.
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Raw binary, programmed at the offset
+    Bin,
+    /// Intel HEX
+    Ihex,
+    /// Motorola S-record
+    Srec,
+    /// ELF executable, programming the loadable segments
+    Elf,
+}
+
+impl ImageFormat {
+    /// Guess the format from the file extension, defaulting to binary.
+    #[must_use]
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        let extension = path
+            .as_ref()
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("hex" | "ihex" | "ihx") => Self::Ihex,
+            Some("srec" | "s19" | "s28" | "s37" | "mot") => Self::Srec,
+            Some("elf") => Self::Elf,
+            _ => Self::Bin,
+        }
+    }
+
+    /// Load a file as regions of flash. Address `base` in the file
+    /// is mapped to flash address `offset`, and binary files are placed at `offset`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file cannot be read or parsed,
+    /// or contains addresses below `base`.
+    #[instrument(skip(path))]
+    pub fn load(
+        self,
+        path: impl AsRef<Path>,
+        base: usize,
+        offset: usize,
+    ) -> Result<Regions, Error> {
+        let regions = match self {
+            Self::Bin => {
+                let mut regions = Regions::new();
+                regions.add(offset, fs::read(path)?)?;
+                return Ok(regions);
+            }
+            Self::Ihex => ihex::parse(&fs::read_to_string(path)?)?,
+            Self::Srec => srec::parse(&fs::read_to_string(path)?)?,
+            Self::Elf => elf::parse(&fs::read(path)?)?,
+        };
+        regions.relocate(base, offset)
+    }
+}
+
+/// Decode a string of hex digit pairs.
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(ImageFormat::from_path("fw.HEX"), ImageFormat::Ihex);
+        assert_eq!(ImageFormat::from_path("fw.srec"), ImageFormat::Srec);
+        assert_eq!(ImageFormat::from_path("top.bin"), ImageFormat::Bin);
+        assert_eq!(ImageFormat::from_path("fw.elf"), ImageFormat::Elf);
+        assert_eq!(ImageFormat::from_path("top"), ImageFormat::Bin);
+    }
+
+    #[test]
+    fn test_decode_hex() {
+        assert_eq!(decode_hex("00aF10"), Some(vec![0, 0xaf, 0x10]));
+        assert_eq!(decode_hex("0"), None);
+        assert_eq!(decode_hex("0g"), None);
+        assert_eq!(decode_hex("+1"), None);
+    }
+}
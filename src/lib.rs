@@ -1,14 +1,60 @@
+//! Program and read the SPI flash of iceFUN FPGA boards.
+//!
+//! Open a board with [`open_board`], hold its FPGA in reset with [`Device::prepare`],
+//! then use the returned [`DeviceInReset`] through the [`Programmable`] and
+//! [`Dumpable`] traits, or with [`FPGAProg`] and [`FPGADump`] for whole images.
+//! [`Device::new`] accepts any `Read + Write + 'static` transport, and the raw
+//! firmware commands are in [`cmds`].
+//!
+//! ```no_run
+//! use icefunprog::{open_board, Device, Programmable};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let (_, mut fpga) = Device::new(open_board("/dev/ttyACM0")?).prepare()?;
+//! fpga.erase64k(0)?;
+//! fpga.program_page(0, &[0xff; 16])?;
+//! fpga.release()?;
+//! # Ok(())
+//! # }
+//! ```
+
 #![allow(clippy::missing_errors_doc)]
 #![allow(clippy::missing_panics_doc)]
 
-mod cmds;
+mod bitstream;
+mod cancel;
+pub mod cli;
+pub mod cmds;
 mod dev;
+mod digest;
+mod dumpfmt;
+mod elf;
 mod err;
+mod flash;
+mod ihex;
+mod image;
+mod manifest;
+mod multiboot;
 mod programmer;
+mod progress;
+mod regions;
 mod serialport;
+mod srec;
 mod test_mocks;
 mod utils;
 
-pub use dev::Device;
-pub use programmer::{FPGADump, FPGAProg};
-pub use utils::{parse_addr, CommonArgs};
+pub use bitstream::{Bitstream, Frame, Part};
+pub use cancel::CancelToken;
+pub use dev::{Device, DeviceInReset, Dumpable, FlashDevice, Programmable};
+pub use digest::Digest;
+pub use dumpfmt::{DumpFormat, Formatter};
+pub use err::Error;
+pub use flash::{FlashGeometry, FlashId, KnownFlash, KNOWN_FLASH};
+pub use image::ImageFormat;
+pub use manifest::{Manifest, ManifestEntry, ManifestOffset};
+pub use multiboot::{Multiboot, MAX_IMAGES};
+pub use programmer::{blank_check, erase_range, read_bitstream, FPGADump, FPGAProg, Mismatch};
+pub use progress::{LogProgress, Phase, Progress, ProgressReport};
+pub use regions::{Region, Regions};
+pub use serialport::SerialPort;
+pub use utils::{find_boards, open_board, parse_addr, BoardArgs, BoardInfo, CommonArgs, LogArgs};
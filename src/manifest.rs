@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tracing::{info, instrument};
+
+use crate::err::Error;
+use crate::regions::Regions;
+use crate::utils::parse_addr;
+
+/// A flash address in a manifest, either a number or a string
+/// with an optional `K` or `M` suffix.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ManifestOffset {
+    Bytes(usize),
+    Text(String),
+}
+
+impl ManifestOffset {
+    /// # Errors
+    ///
+    /// Will return `Err` if the string is not a valid address.
+    pub fn bytes(&self) -> Result<usize, Error> {
+        match self {
+            Self::Bytes(bytes) => Ok(*bytes),
+            Self::Text(text) => {
+                parse_addr(text).map_err(|err| Error::Manifest(format!("offset {text}: {err}")))
+            }
+        }
+    }
+}
+
+/// A file to program, as listed in a manifest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestEntry {
+    /// Path to the file, relative to the manifest
+    pub file: PathBuf,
+    /// Flash address
+    pub offset: ManifestOffset,
+}
+
+/// A JSON list of files to program together, for example:
+///
+/// ```json
+/// {
+///   "regions": [
+///     { "file": "top.bin", "offset": "0" },
+///     { "file": "firmware.bin", "offset": "768K" },
+///     { "file": "data.bin", "offset": 1048576 }
+///   ]
+/// }
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub regions: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// # Errors
+    ///
+    /// Will return `Err` if the manifest cannot be read or parsed.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        serde_json::from_str(&text).map_err(|err| Error::Manifest(err.to_string()))
+    }
+
+    /// Load the listed files, resolving relative paths against `dir`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a file cannot be read, an offset is invalid,
+    /// or the files overlap.
+    #[instrument(skip(self))]
+    pub fn load(&self, dir: &Path) -> Result<Regions, Error> {
+        let mut regions = Regions::new();
+        for entry in &self.regions {
+            let offset = entry.offset.bytes()?;
+            let data = fs::read(dir.join(&entry.file))?;
+            info!(file = ?entry.file, offset, len = data.len(), "Loaded");
+            regions.add(offset, data)?;
+        }
+        Ok(regions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let manifest: Manifest = serde_json::from_str(
+            r#"{"regions": [
+                {"file": "a.bin", "offset": "0"},
+                {"file": "b.bin", "offset": "768K"},
+                {"file": "c.bin", "offset": 786432}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(manifest.regions.len(), 3);
+        assert_eq!(manifest.regions[1].file, PathBuf::from("b.bin"));
+        assert_eq!(manifest.regions[1].offset.bytes().unwrap(), 0xc_0000);
+        assert_eq!(manifest.regions[2].offset, ManifestOffset::Bytes(0xc_0000));
+        assert!(ManifestOffset::Text("12Q".into()).bytes().is_err());
+        assert!(serde_json::from_str::<Manifest>(r#"{"regions": [{"file": "a.bin"}]}"#).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use tracing::{info, instrument};
+
+use crate::err::Error;
+
+/// Number of images selectable by warmboot.
+pub const MAX_IMAGES: usize = 4;
+const APPLET_SIZE: usize = 32;
+/// The power on applet, followed by one applet per image.
+const HEADER_SIZE: usize = (1 + MAX_IMAGES) * APPLET_SIZE;
+const MAX_ADDR: usize = 0xff_ffff;
+
+/// Builder for a multiboot flash image, as made by `icemulti`.
+///
+/// A warmboot header of applets at the start of the image points
+/// to up to four bitstreams, which follow at aligned offsets.
+#[derive(Debug)]
+pub struct Multiboot {
+    images: Vec<Vec<u8>>,
+    /// Image offsets are aligned to a multiple of this many bytes
+    pub align: usize,
+    /// Set the cold boot flag, so that the CBSEL pins select the image at power on
+    pub coldboot: bool,
+    /// Index of the image booted at power on
+    pub por_image: usize,
+}
+
+fn applet(addr: usize, coldboot: bool) -> [u8; APPLET_SIZE] {
+    let mut applet = [0u8; APPLET_SIZE];
+    let [_, addr_hi, addr_mid, addr_lo] = u32::try_from(addr)
+        .expect("address checked by build")
+        .to_be_bytes();
+    let commands = [
+        // Preamble
+        0x7e,
+        0xaa,
+        0x99,
+        0x7e,
+        // Boot mode
+        0x92,
+        0x00,
+        if coldboot { 0x10 } else { 0x00 },
+        // Boot address, with SPI normal read command (0x03)
+        0x44,
+        0x03,
+        addr_hi,
+        addr_mid,
+        addr_lo,
+        // Bank offset
+        0x82,
+        0x00,
+        0x00,
+        // Reboot
+        0x01,
+        0x08,
+    ];
+    applet[..commands.len()].copy_from_slice(&commands);
+    applet
+}
+
+impl Multiboot {
+    #[must_use]
+    pub fn new(align: usize) -> Self {
+        Self {
+            images: vec![],
+            align,
+            coldboot: false,
+            por_image: 0,
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if there are already [`MAX_IMAGES`] images.
+    pub fn add_image(&mut self, image: Vec<u8>) -> Result<(), Error> {
+        if self.images.len() == MAX_IMAGES {
+            return Err(Error::Multiboot(format!(
+                "At most {MAX_IMAGES} images are supported"
+            )));
+        }
+        self.images.push(image);
+        Ok(())
+    }
+
+    /// Offsets of each image, relative to the start of the header.
+    #[must_use]
+    pub fn offsets(&self) -> Vec<usize> {
+        let mut next = HEADER_SIZE;
+        self.images
+            .iter()
+            .map(|image| {
+                let offset = next.next_multiple_of(self.align);
+                next = offset + image.len();
+                offset
+            })
+            .collect()
+    }
+
+    /// Build the flash image, for programming at flash address `base`.
+    /// Gaps between images are filled with `0xff`.
+    /// The iCE40 only boots from a header at address 0, so other bases are
+    /// for images which another header reboots into.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if there are no images, `por_image` is out of range,
+    /// `align` is zero, or the images do not fit in the 24 bit address space.
+    #[instrument(skip(self))]
+    pub fn build(&self, base: usize) -> Result<Vec<u8>, Error> {
+        if self.align == 0 {
+            return Err(Error::Multiboot("Alignment of 0 bytes".into()));
+        }
+        if self.por_image >= self.images.len() {
+            return Err(Error::Multiboot(format!(
+                "Power on image {} of {} images",
+                self.por_image,
+                self.images.len()
+            )));
+        }
+        let offsets = self.offsets();
+        let len =
+            offsets.last().copied().unwrap_or(HEADER_SIZE) + self.images.last().map_or(0, Vec::len);
+        if base + len > MAX_ADDR + 1 {
+            return Err(Error::Multiboot(format!(
+                "{len} byte image at {base:#x} exceeds 24 bit addresses"
+            )));
+        }
+        let mut data = vec![0xff; len];
+        let por_addr = base + offsets[self.por_image];
+        data[..APPLET_SIZE].copy_from_slice(&applet(por_addr, self.coldboot));
+        for slot in 0..MAX_IMAGES {
+            // Unused slots boot the first image
+            let addr = base + offsets.get(slot).unwrap_or(&offsets[0]);
+            let start = (1 + slot) * APPLET_SIZE;
+            data[start..start + APPLET_SIZE].copy_from_slice(&applet(addr, self.coldboot));
+        }
+        for (image, offset) in self.images.iter().zip(offsets) {
+            info!(offset = base + offset, len = image.len(), "Image");
+            data[offset..offset + image.len()].copy_from_slice(image);
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitstream::Bitstream;
+
+    #[test]
+    fn test_offsets() {
+        let mut multiboot = Multiboot::new(0x100);
+        multiboot.add_image(vec![1; 0x100]).unwrap();
+        multiboot.add_image(vec![2; 0x101]).unwrap();
+        multiboot.add_image(vec![3; 1]).unwrap();
+        assert_eq!(multiboot.offsets(), vec![0x100, 0x200, 0x400]);
+        multiboot.add_image(vec![4; 1]).unwrap();
+        assert!(multiboot.add_image(vec![5; 1]).is_err());
+    }
+
+    #[test]
+    fn test_build() {
+        let mut multiboot = Multiboot::new(0x1000);
+        multiboot.add_image(vec![1; 0x10]).unwrap();
+        multiboot.add_image(vec![2; 0x10]).unwrap();
+        multiboot.por_image = 1;
+        let data = multiboot.build(0x20000).unwrap();
+        assert_eq!(data.len(), 0x2010);
+        assert_eq!(data[..APPLET_SIZE], applet(0x22000, false));
+        assert_eq!(data[APPLET_SIZE..2 * APPLET_SIZE], applet(0x21000, false));
+        assert_eq!(data[4 * APPLET_SIZE..HEADER_SIZE], applet(0x21000, false));
+        assert_eq!(data[HEADER_SIZE], 0xff);
+        assert_eq!(data[0x1000..0x1010], [1; 0x10]);
+        assert_eq!(data[0x2000..], [2; 0x10]);
+        multiboot.por_image = 2;
+        assert!(multiboot.build(0).is_err());
+        multiboot.por_image = 0;
+        multiboot.align = 0;
+        assert!(multiboot.build(0).is_err());
+    }
+
+    #[test]
+    fn test_applet() {
+        let data = applet(0x12_3456, true);
+        let bitstream = Bitstream::parse(data.as_slice()).unwrap();
+        assert!(bitstream.reboot);
+        assert_eq!(data[9..12], [0x12, 0x34, 0x56]);
+        assert_eq!(data[6], 0x10);
+    }
+}
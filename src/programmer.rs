@@ -1,14 +1,19 @@
 use std::cmp::min;
+use std::fmt::Display;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::time::{Duration, Instant};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
 use std::{fs, path::Path};
 
-use tracing::{info, instrument};
+use tracing::{info, instrument, warn};
 
-use crate::cmds::PAGE_SIZE;
-use crate::dev::{Dumpable, Programmable};
+use crate::bitstream::Bitstream;
+use crate::cancel::CancelToken;
+use crate::dev::{Dumpable, FlashDevice, Programmable};
+use crate::digest::{Digest, Hasher};
+use crate::dumpfmt::{DumpFormat, Formatter};
 use crate::err::Error;
+use crate::flash::FlashGeometry;
+use crate::progress::{LogProgress, Phase, Progress, ProgressReport, Tracker};
 
 #[derive(Copy, Clone, Debug)]
 struct Range {
@@ -16,49 +21,123 @@ struct Range {
     len: usize,
 }
 
-const REPORT_PERIOD: Duration = Duration::from_secs(1);
-
 impl Range {
     fn new(start: usize, len: usize) -> Self {
         Self { start, len }
     }
+
+    fn contains(&self, addr: usize) -> bool {
+        (self.start..self.start + self.len).contains(&addr)
+    }
+    /// # Errors
+    ///
+    /// Will return `Err` if the range does not fit in the flash.
+    fn check(&self, geometry: &FlashGeometry) -> Result<(), Error> {
+        if self.start + self.len > geometry.capacity {
+            return Err(Error::Range(format!(
+                "{} bytes at {:#x} overruns {} byte flash",
+                self.len, self.start, geometry.capacity
+            )));
+        }
+        Ok(())
+    }
+
+    /// Erase blocks covering this range.
+    ///
     /// # Errors
     ///
     /// Will return `Err` if addresses are out of range.
-    fn sectors(&self) -> Result<impl Iterator<Item = u8>, Error> {
-        let start_sector = u8::try_from(self.start >> 16)?;
-        let end_sector = u8::try_from((self.start + self.len) >> 16)?;
-        Ok(start_sector..=end_sector)
-    }
-
-    #[instrument]
-    fn pages<'a, const N: usize>(&'a self) -> impl Iterator<Item = Range> {
-        fn inner(
-            page_size: usize,
-            page_count: usize,
-            start_addr: usize,
-            end_addr: usize,
-        ) -> impl Iterator<Item = Range> {
-            let mut last_tick = Instant::now();
-            (0..page_count).map(move |page| {
-                let now = Instant::now();
-                if now.duration_since(last_tick) >= REPORT_PERIOD || (1 + page) == page_count {
-                    let progress = format!("{}%", (100 * (1 + page)) / page_count);
-                    info!(progress);
-                    last_tick = now;
-                }
-                let start = start_addr + (page * page_size);
-                Range::new(start, min(page_size, end_addr - start))
+    fn sectors(&self, block_size: usize) -> Result<impl Iterator<Item = u8>, Error> {
+        let end = (self.start + self.len).div_ceil(block_size);
+        let sectors = (self.start / block_size..end)
+            .map(u8::try_from)
+            .collect::<Result<Vec<u8>, _>>()?;
+        Ok(sectors.into_iter())
+    }
+
+    /// Split this range at erase block boundaries.
+    fn blocks(&self, block_size: usize) -> impl Iterator<Item = Range> {
+        let end = self.start + self.len;
+        let mut start = self.start;
+        std::iter::from_fn(move || {
+            (start < end).then(|| {
+                let block_end = min(end, (start / block_size + 1) * block_size);
+                let block = Range::new(start, block_end - start);
+                start = block_end;
+                block
             })
+        })
+    }
+
+    fn pages(&self, page_size: usize) -> impl Iterator<Item = Range> {
+        let end = self.start + self.len;
+        (self.start..end)
+            .step_by(page_size)
+            .map(move |start| Range::new(start, min(page_size, end - start)))
+    }
+}
+
+/// Part of an image which the input does not define, between merged regions.
+#[derive(Clone, Debug)]
+struct Gap {
+    range: Range,
+    /// Flash contents read by `preserve`, programmed instead of the `0xff` filler
+    preserved: Vec<u8>,
+}
+
+/// Replace the bytes of `buf`, read from the image at `addr`, which lie in preserved gaps.
+fn overlay_gaps(gaps: &[Gap], addr: usize, buf: &mut [u8]) {
+    for gap in gaps.iter().filter(|gap| !gap.preserved.is_empty()) {
+        let start = gap.range.start.max(addr);
+        let end = (gap.range.start + gap.range.len).min(addr + buf.len());
+        if start < end {
+            buf[start - addr..end - addr]
+                .copy_from_slice(&gap.preserved[start - gap.range.start..end - gap.range.start]);
         }
-        let page_count = 1 + ((self.len - 1) / N);
-        inner(N, page_count, self.start, self.start + self.len)
+    }
+}
+
+/// A run of bytes where the flash differs from the image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub addr: usize,
+    /// Bytes in the image
+    pub expected: Vec<u8>,
+    /// Bytes read from the flash
+    pub actual: Vec<u8>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const SHOWN: usize = 8;
+        let len = self.expected.len();
+        let more = if len > SHOWN { "..." } else { "" };
+        write!(
+            f,
+            "{:#08x}..{:#08x} ({len} bytes): expected {:02x?}{more}, read {:02x?}{more}",
+            self.addr,
+            self.addr + len,
+            &self.expected[..min(len, SHOWN)],
+            &self.actual[..min(len, SHOWN)],
+        )
     }
 }
 
 pub struct FPGAProg<R: Read + Seek> {
     reader: R,
     range: Range,
+    /// Preserved flash contents before `range`, in the first erase block
+    head: Vec<u8>,
+    /// Preserved flash contents after `range`, in the last erase block
+    tail: Vec<u8>,
+    /// Erase blocks which already hold the image, and are skipped
+    unchanged: Vec<u8>,
+    /// Parts of the image which the input does not define, between merged regions
+    gaps: Vec<Gap>,
+    /// Digest of the image, from the last `program` or `verify`
+    digest: Option<Digest>,
+    progress: Box<dyn Progress + Send>,
+    cancel: CancelToken,
 }
 
 impl FPGAProg<File> {
@@ -68,61 +147,449 @@ impl FPGAProg<File> {
     pub fn from_path(path: impl AsRef<Path>, offset: usize) -> Result<Self, Error> {
         let meta = fs::metadata(&path)?;
         let file = File::open(&path)?;
-        Ok(Self {
-            reader: file,
-            range: Range::new(offset, usize::try_from(meta.len())?),
-        })
+        Ok(Self::new(
+            file,
+            Range::new(offset, usize::try_from(meta.len())?),
+        ))
+    }
+}
+
+impl FPGAProg<Cursor<Vec<u8>>> {
+    /// Program an image held in memory.
+    #[must_use]
+    pub fn from_bytes(data: Vec<u8>, offset: usize) -> Self {
+        let len = data.len();
+        Self::new(Cursor::new(data), Range::new(offset, len))
     }
 }
 
 impl<R: Read + Seek> FPGAProg<R> {
+    fn new(reader: R, range: Range) -> Self {
+        Self {
+            reader,
+            range,
+            head: vec![],
+            tail: vec![],
+            unchanged: vec![],
+            gaps: vec![],
+            digest: None,
+            progress: Box::new(LogProgress::default()),
+            cancel: CancelToken::new(),
+        }
+    }
+
+    /// Report the progress of `erase`, `program`, `verify` and `compare` to `progress`,
+    /// instead of logging it.
+    pub fn set_progress(&mut self, progress: Box<dyn Progress + Send>) {
+        self.progress = progress;
+    }
+
+    /// Stop operations between pages once `cancel` is cancelled.
+    pub fn set_cancel(&mut self, cancel: CancelToken) {
+        self.cancel = cancel;
+    }
+
+    /// Mark `len` bytes at `start` as not defined by the input, so that `compare` ignores them.
+    pub(crate) fn add_gap(&mut self, start: usize, len: usize) {
+        self.gaps.push(Gap {
+            range: Range::new(start, len),
+            preserved: vec![],
+        });
+    }
+
+    /// Digest of the image, computed as it is programmed or verified.
+    /// Preserved flash contents are not included.
+    #[must_use]
+    pub fn digest(&self) -> Option<Digest> {
+        self.digest
+    }
+
+    /// Check that the image fits in the flash, before anything is erased.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the image overruns the flash.
+    pub fn check(&self, fpga: &impl FlashDevice) -> Result<(), Error> {
+        self.program_range().check(fpga.geometry())
+    }
+
+    /// Parse the image as an iCE40 bitstream.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the image is not a valid bitstream.
+    pub fn bitstream(&mut self) -> Result<Bitstream, Error> {
+        self.reader.seek(SeekFrom::Start(0))?;
+        Bitstream::parse(&mut self.reader)
+    }
+
+    /// Read the contents of the erase blocks that lie outside the image,
+    /// and of gaps between merged regions, so that `program` writes them back after `erase`.
+    ///
     /// # Errors
     ///
     /// Will return `Err` if commnication fails.
     #[instrument(skip_all)]
-    pub fn erase(&self, fpga: &mut impl Programmable) -> Result<(), Error> {
-        for sector in self.range.sectors()? {
-            info!(sector, "Erasing");
-            fpga.erase64k(sector)?;
+    pub fn preserve(&mut self, fpga: &mut impl Dumpable) -> Result<(), Error> {
+        let geometry = *fpga.geometry();
+        self.range.check(&geometry)?;
+        let end = self.range.start + self.range.len;
+        let head_start = self.range.start - (self.range.start % geometry.block_size);
+        let tail_end = min(end.next_multiple_of(geometry.block_size), geometry.capacity);
+        info!(
+            head = self.range.start - head_start,
+            tail = tail_end - end,
+            "Preserving"
+        );
+        let head = Range::new(head_start, self.range.start - head_start);
+        self.head = read_range(fpga, head, &self.cancel)?;
+        self.tail = read_range(fpga, Range::new(end, tail_end - end), &self.cancel)?;
+        for gap in &mut self.gaps {
+            gap.preserved = read_range(fpga, gap.range, &self.cancel)?;
         }
         Ok(())
     }
 
+    /// Compare the flash with the image, one erase block at a time, so that
+    /// `erase`, `program` and `verify` skip blocks which are unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if commnication fails.
+    #[instrument(skip_all)]
+    pub fn skip_unchanged(&mut self, fpga: &mut impl Dumpable) -> Result<(), Error> {
+        let geometry = *fpga.geometry();
+        let range = self.program_range();
+        range.check(&geometry)?;
+        self.reader.seek(SeekFrom::Start(0))?;
+        let mut reader = self
+            .head
+            .as_slice()
+            .chain(&mut self.reader)
+            .chain(self.tail.as_slice());
+        self.unchanged.clear();
+        let mut changed = 0;
+        for block in range.blocks(geometry.block_size) {
+            let mut image = vec![0u8; block.len];
+            reader.read_exact(&mut image)?;
+            overlay_gaps(&self.gaps, block.start, &mut image);
+            if read_range(fpga, block, &self.cancel)? == image {
+                self.unchanged
+                    .push(u8::try_from(block.start / geometry.block_size)?);
+            } else {
+                changed += 1;
+            }
+        }
+        info!(changed, unchanged = self.unchanged.len(), "Compared blocks");
+        Ok(())
+    }
+
+    /// The range written by `program`, including preserved data.
+    fn program_range(&self) -> Range {
+        Range::new(
+            self.range.start - self.head.len(),
+            self.head.len() + self.range.len + self.tail.len(),
+        )
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if commnication fails.
+    #[instrument(skip_all)]
+    pub fn erase(&mut self, fpga: &mut impl Programmable) -> Result<(), Error> {
+        let geometry = *fpga.geometry();
+        self.range.check(&geometry)?;
+        let sectors: Vec<u8> = self
+            .range
+            .sectors(geometry.block_size)?
+            .filter(|sector| !self.unchanged.contains(sector))
+            .collect();
+        erase_sectors(fpga, &sectors, self.progress.as_mut(), &self.cancel)
+    }
+
+    /// Apply `action` to each page of the image, except for pages in unchanged blocks
+    /// and, if `skip_blank`, blank pages, and compute the digest of the image.
+    /// Returns the number of blank pages skipped.
     fn do_pages(
         &mut self,
+        geometry: &FlashGeometry,
+        phase: Phase,
+        skip_blank: bool,
         mut action: impl FnMut(usize, &[u8]) -> Result<(), Error>,
-    ) -> Result<(), Error> {
-        let mut buf = [0u8; PAGE_SIZE];
-        for Range { start, len } in self.range.pages::<PAGE_SIZE>() {
+    ) -> Result<usize, Error> {
+        let range = self.program_range();
+        range.check(geometry)?;
+        let tracker = Tracker::new(phase, range.len, &self.cancel);
+        let image_start = self.range.start;
+        let image_end = image_start + self.range.len;
+        let mut hasher = Hasher::default();
+        let mut reader = self
+            .head
+            .as_slice()
+            .chain(&mut self.reader)
+            .chain(self.tail.as_slice());
+        let mut buf = vec![0u8; geometry.page_size];
+        let mut blank = 0;
+        for Range { start, len } in range.pages(geometry.page_size) {
+            tracker.step(self.progress.as_mut(), start - range.start)?;
             let part_buf = &mut buf[..len];
-            self.reader.read_exact(part_buf)?;
+            reader.read_exact(part_buf)?;
+            let image_part = image_start.saturating_sub(start).min(len)
+                ..image_end.saturating_sub(start).min(len);
+            hasher.update(&part_buf[image_part]);
+            overlay_gaps(&self.gaps, start, part_buf);
+            if self
+                .unchanged
+                .contains(&u8::try_from(start / geometry.block_size)?)
+            {
+                continue;
+            }
+            if skip_blank && part_buf.iter().all(|&b| b == 0xff) {
+                blank += 1;
+                continue;
+            }
             action(start, part_buf)?;
         }
-        Ok(())
+        tracker.report(self.progress.as_mut(), range.len);
+        info!(blank, "Skipped blank pages");
+        self.digest = Some(hasher.finish());
+        Ok(blank)
     }
 
+    /// Returns the number of blank pages skipped, which are left erased.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if commnication fails.
+    #[instrument(skip_all)]
+    pub fn program(&mut self, fpga: &mut impl Programmable) -> Result<usize, Error> {
+        let geometry = *fpga.geometry();
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.do_pages(&geometry, Phase::Program, true, |addr, data| {
+            fpga.program_page(addr, data)
+        })
+    }
+
+    /// Returns the number of blank pages skipped, which are left erased.
+    ///
     /// # Errors
     ///
     /// Will return `Err` if commnication fails.
     #[instrument(skip_all)]
-    pub fn program(&mut self, fpga: &mut impl Programmable) -> Result<(), Error> {
+    pub fn verify(&mut self, fpga: &mut impl Programmable) -> Result<usize, Error> {
+        let geometry = *fpga.geometry();
         self.reader.seek(SeekFrom::Start(0))?;
-        self.do_pages(|addr, data| fpga.program_page(addr, data))
+        self.do_pages(&geometry, Phase::Verify, true, |addr, data| {
+            fpga.verify_page(addr, data)
+        })
     }
 
+    /// Verify every page of the image, including blank pages, for flash which was
+    /// not just erased and programmed. Gaps between merged regions are read from
+    /// the flash first, so they always match.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if commnication fails, or a page differs.
+    #[instrument(skip_all)]
+    pub fn verify_all(&mut self, fpga: &mut (impl Programmable + Dumpable)) -> Result<(), Error> {
+        let geometry = *fpga.geometry();
+        for gap in &mut self.gaps {
+            gap.preserved = read_range(fpga, gap.range, &self.cancel)?;
+        }
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.do_pages(&geometry, Phase::Verify, false, |addr, data| {
+            fpga.verify_page(addr, data)
+        })?;
+        Ok(())
+    }
+
+    /// Compare the flash with the image, without erasing or programming.
+    /// Returns each run of differing bytes, ignoring gaps between merged regions.
+    ///
     /// # Errors
     ///
     /// Will return `Err` if commnication fails.
     #[instrument(skip_all)]
-    pub fn verify(&mut self, fpga: &mut impl Programmable) -> Result<(), Error> {
+    pub fn compare(&mut self, fpga: &mut impl Dumpable) -> Result<Vec<Mismatch>, Error> {
+        let geometry = *fpga.geometry();
+        self.range.check(&geometry)?;
         self.reader.seek(SeekFrom::Start(0))?;
-        self.do_pages(|addr, data| fpga.verify_page(addr, data))
+        let tracker = Tracker::new(Phase::Compare, self.range.len, &self.cancel);
+        let mut mismatches: Vec<Mismatch> = vec![];
+        let mut buf = vec![0u8; geometry.page_size];
+        for Range { start, len } in self.range.pages(geometry.page_size) {
+            tracker.step(self.progress.as_mut(), start - self.range.start)?;
+            let image = &mut buf[..len];
+            self.reader.read_exact(image)?;
+            let mut flash = Vec::with_capacity(len);
+            fpga.read_page(start, len, &mut flash)?;
+            if flash == image {
+                continue;
+            }
+            let mut differ = 0;
+            for (i, (&expected, &actual)) in image.iter().zip(&flash).enumerate() {
+                let addr = start + i;
+                if expected == actual || self.gaps.iter().any(|gap| gap.range.contains(addr)) {
+                    continue;
+                }
+                differ += 1;
+                match mismatches.last_mut() {
+                    Some(last) if last.addr + last.expected.len() == addr => {
+                        last.expected.push(expected);
+                        last.actual.push(actual);
+                    }
+                    _ => mismatches.push(Mismatch {
+                        addr,
+                        expected: vec![expected],
+                        actual: vec![actual],
+                    }),
+                }
+            }
+            if differ > 0 {
+                warn!(page = start, differ, "Page differs");
+            }
+        }
+        tracker.report(self.progress.as_mut(), self.range.len);
+        Ok(mismatches)
+    }
+}
+
+/// Check that `size` bytes of flash from `offset` are erased, or up to the end
+/// of the flash if `size` is `None`. Returns the addresses of pages which are not blank.
+///
+/// # Errors
+///
+/// Will return `Err` if commnication fails, or the range is outside the flash.
+#[instrument(skip(fpga, progress, cancel))]
+pub fn blank_check(
+    fpga: &mut impl Dumpable,
+    offset: usize,
+    size: Option<usize>,
+    progress: &mut dyn Progress,
+    cancel: &CancelToken,
+) -> Result<Vec<usize>, Error> {
+    let geometry = *fpga.geometry();
+    let range = Range::new(
+        offset,
+        size.unwrap_or_else(|| geometry.capacity.saturating_sub(offset)),
+    );
+    range.check(&geometry)?;
+    let tracker = Tracker::new(Phase::BlankCheck, range.len, cancel);
+    let mut not_blank = vec![];
+    let mut page = Vec::with_capacity(geometry.page_size);
+    for Range { start, len } in range.pages(geometry.page_size) {
+        tracker.step(progress, start - range.start)?;
+        page.clear();
+        fpga.read_page(start, len, &mut page)?;
+        if page.iter().any(|&b| b != 0xff) {
+            warn!(page = start, "Not blank");
+            not_blank.push(start);
+        }
+    }
+    tracker.report(progress, range.len);
+    info!(not_blank = not_blank.len(), "Blank check");
+    Ok(not_blank)
+}
+
+/// Erase the blocks covering `size` bytes of flash from `offset`.
+/// Whole erase blocks are erased, including any data outside the range.
+///
+/// # Errors
+///
+/// Will return `Err` if commnication fails, or the range is outside the flash.
+#[instrument(skip(fpga, progress, cancel))]
+pub fn erase_range(
+    fpga: &mut impl Programmable,
+    offset: usize,
+    size: usize,
+    progress: &mut dyn Progress,
+    cancel: &CancelToken,
+) -> Result<(), Error> {
+    let geometry = *fpga.geometry();
+    let range = Range::new(offset, size);
+    range.check(&geometry)?;
+    let sectors: Vec<u8> = range.sectors(geometry.block_size)?.collect();
+    erase_sectors(fpga, &sectors, progress, cancel)
+}
+
+fn erase_sectors(
+    fpga: &mut impl Programmable,
+    sectors: &[u8],
+    progress: &mut (impl Progress + ?Sized),
+    cancel: &CancelToken,
+) -> Result<(), Error> {
+    let block_size = fpga.geometry().block_size;
+    let tracker = Tracker::new(Phase::Erase, sectors.len() * block_size, cancel);
+    for (i, &sector) in sectors.iter().enumerate() {
+        tracker.step(progress, i * block_size)?;
+        info!(sector, "Erasing");
+        fpga.erase64k(sector)?;
     }
+    tracker.report(progress, sectors.len() * block_size);
+    Ok(())
+}
+
+/// Reads the flash sequentially, a page at a time.
+struct FlashReader<'a, D: Dumpable> {
+    fpga: &'a mut D,
+    addr: usize,
+}
+
+impl<D: Dumpable> Read for FlashReader<'_, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let geometry = *self.fpga.geometry();
+        let page_end = (self.addr / geometry.page_size + 1) * geometry.page_size;
+        let len = min(
+            buf.len(),
+            min(page_end, geometry.capacity).saturating_sub(self.addr),
+        );
+        if len > 0 {
+            let mut output = &mut buf[..len];
+            self.fpga
+                .read_page(self.addr, len, &mut output)
+                .map_err(io::Error::other)?;
+            self.addr += len;
+        }
+        Ok(len)
+    }
+}
+
+/// Parse the bitstream stored in flash at `offset`, for example
+/// to find its length.
+///
+/// # Errors
+///
+/// Will return `Err` if commnication fails, or the flash does not hold a valid bitstream.
+#[instrument(skip(fpga))]
+pub fn read_bitstream(fpga: &mut impl Dumpable, offset: usize) -> Result<Bitstream, Error> {
+    let bitstream = Bitstream::parse(FlashReader { fpga, addr: offset })?;
+    info!(len = bitstream.len, part = ?bitstream.part(), "Bitstream in flash");
+    Ok(bitstream)
+}
+
+fn read_range(
+    fpga: &mut impl Dumpable,
+    range: Range,
+    cancel: &CancelToken,
+) -> Result<Vec<u8>, Error> {
+    let mut dumper = FPGADump::new(vec![], range.start, range.len, DumpFormat::Raw);
+    dumper.set_progress(Box::new(|_: &ProgressReport| {}));
+    dumper.set_cancel(cancel.clone());
+    if range.len > 0 {
+        dumper.dump(fpga)?;
+    }
+    Ok(dumper.writer)
 }
 
 pub struct FPGADump<W: Write> {
     writer: W,
     range: Range,
+    format: DumpFormat,
+    /// Digest of the bytes read by the last `dump`
+    digest: Option<Digest>,
+    progress: Box<dyn Progress + Send>,
+    cancel: CancelToken,
 }
 
 impl FPGADump<File> {
@@ -131,22 +598,384 @@ impl FPGADump<File> {
     /// Will return `Err` if the path cannot be accessed.
     pub fn from_path(path: impl AsRef<Path>, offset: usize, size: usize) -> Result<Self, Error> {
         let file = std::fs::File::create(path)?;
-        Ok(Self {
-            writer: file,
-            range: Range::new(offset, size),
-        })
+        Ok(Self::new(file, offset, size, DumpFormat::Raw))
     }
 }
 
 impl<W: Write> FPGADump<W> {
+    #[must_use]
+    pub fn new(writer: W, offset: usize, size: usize, format: DumpFormat) -> Self {
+        Self {
+            writer,
+            range: Range::new(offset, size),
+            format,
+            digest: None,
+            progress: Box::new(LogProgress::default()),
+            cancel: CancelToken::new(),
+        }
+    }
+
+    /// Report the progress of `dump` to `progress`, instead of logging it.
+    pub fn set_progress(&mut self, progress: Box<dyn Progress + Send>) {
+        self.progress = progress;
+    }
+
+    /// Stop dumping between pages once `cancel` is cancelled.
+    pub fn set_cancel(&mut self, cancel: CancelToken) {
+        self.cancel = cancel;
+    }
+
+    /// Digest of the flash contents, computed as they are dumped.
+    #[must_use]
+    pub fn digest(&self) -> Option<Digest> {
+        self.digest
+    }
+
     /// # Errors
     ///
     /// Will return `Err` if commnication fails.
     #[instrument(skip_all)]
     pub fn dump(&mut self, fpga: &mut impl Dumpable) -> Result<(), Error> {
-        for Range { start, len } in self.range.pages::<PAGE_SIZE>() {
-            fpga.read_page(start, len, &mut self.writer)?;
+        let geometry = *fpga.geometry();
+        self.range.check(&geometry)?;
+        let mut formatter = Formatter::new(&mut self.writer, self.format, self.range.start)?;
+        let tracker = Tracker::new(Phase::Dump, self.range.len, &self.cancel);
+        let mut hasher = Hasher::default();
+        let mut page = Vec::with_capacity(geometry.page_size);
+        for Range { start, len } in self.range.pages(geometry.page_size) {
+            tracker.step(self.progress.as_mut(), start - self.range.start)?;
+            page.clear();
+            fpga.read_page(start, len, &mut page)?;
+            hasher.update(&page);
+            formatter.write_all(&page)?;
         }
+        formatter.finish()?;
+        tracker.report(self.progress.as_mut(), self.range.len);
+        self.digest = Some(hasher.finish());
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multiboot::Multiboot;
+    use crate::test_mocks::MockFlash;
+
+    #[test]
+    fn test_sectors() {
+        let block = 64 * 1024;
+        let sectors = |start, len| {
+            Range::new(start, len)
+                .sectors(block)
+                .unwrap()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sectors(0, 1), vec![0]);
+        assert_eq!(sectors(0, block), vec![0]);
+        assert_eq!(sectors(block - 1, 2), vec![0, 1]);
+        assert_eq!(sectors(3 * block, block + 1), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let blocks = Range::new(0x0ff0, 0x2000)
+            .blocks(0x1000)
+            .map(|r| (r.start, r.len))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            blocks,
+            vec![(0x0ff0, 0x10), (0x1000, 0x1000), (0x2000, 0xff0)]
+        );
+        assert_eq!(Range::new(0, 0).blocks(0x1000).count(), 0);
+    }
+
+    #[test]
+    fn test_check() {
+        let geometry = FlashGeometry::default();
+        assert!(Range::new(0, geometry.capacity).check(&geometry).is_ok());
+        assert!(Range::new(1, geometry.capacity).check(&geometry).is_err());
+    }
+
+    #[test]
+    fn test_pages() {
+        let pages = Range::new(0x100, 0x201)
+            .pages(0x100)
+            .map(|r| (r.start, r.len))
+            .collect::<Vec<_>>();
+        assert_eq!(pages, vec![(0x100, 0x100), (0x200, 0x100), (0x300, 1)]);
+        assert_eq!(Range::new(0x100, 0).pages(0x100).count(), 0);
+    }
+
+    #[test]
+    fn test_preserve() {
+        let mut flash = MockFlash::new(FlashGeometry::default());
+        flash.data.fill(0x55);
+        let image = vec![0xaa; 0x10100];
+        let mut programmer = FPGAProg::from_bytes(image, 0x10000);
+        programmer.preserve(&mut flash).unwrap();
+        programmer.erase(&mut flash).unwrap();
+        programmer.program(&mut flash).unwrap();
+        programmer.verify(&mut flash).unwrap();
+        assert!(flash.data[..0x10000].iter().all(|&b| b == 0x55));
+        assert!(flash.data[0x10000..0x20100].iter().all(|&b| b == 0xaa));
+        assert!(flash.data[0x20100..].iter().all(|&b| b == 0x55));
+    }
+
+    #[test]
+    fn test_skip_unchanged() {
+        let mut flash = MockFlash::new(FlashGeometry::default());
+        let mut image = vec![0xaa; 0x30000];
+        flash.data[..image.len()].copy_from_slice(&image);
+        image[0x10010] = 0x55;
+        let mut programmer = FPGAProg::from_bytes(image.clone(), 0);
+        programmer.skip_unchanged(&mut flash).unwrap();
+        assert_eq!(programmer.unchanged, vec![0, 2]);
+        flash.data[0] = 0;
+        programmer.erase(&mut flash).unwrap();
+        programmer.program(&mut flash).unwrap();
+        programmer.verify(&mut flash).unwrap();
+        // Block 0 was skipped, so the corruption is not repaired
+        assert_eq!(flash.data[0], 0);
+        assert_eq!(flash.data[1..image.len()], image[1..]);
+    }
+
+    #[test]
+    fn test_skip_blank() {
+        let mut flash = MockFlash::new(FlashGeometry::default());
+        let mut image = vec![0xff; 0x1000];
+        image[0x100] = 0;
+        let mut programmer = FPGAProg::from_bytes(image.clone(), 0);
+        programmer.erase(&mut flash).unwrap();
+        assert_eq!(programmer.program(&mut flash).unwrap(), 15);
+        assert_eq!(programmer.verify(&mut flash).unwrap(), 15);
+        assert_eq!(flash.data[..image.len()], image);
+        programmer.verify_all(&mut flash).unwrap();
+        flash.data[0x200] = 0;
+        assert_eq!(programmer.verify(&mut flash).unwrap(), 15);
+        assert!(programmer.verify_all(&mut flash).is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut flash = MockFlash::new(FlashGeometry::default());
+        let image = vec![0x55; 0x300];
+        flash.data[0x1000..0x1300].copy_from_slice(&image);
+        flash.data[0x10ff] = 0;
+        flash.data[0x1100] = 0;
+        flash.data[0x1200] = 0;
+        let mut programmer = FPGAProg::from_bytes(image, 0x1000);
+        let mismatches = programmer.compare(&mut flash).unwrap();
+        assert_eq!(
+            mismatches,
+            vec![
+                Mismatch {
+                    addr: 0x10ff,
+                    expected: vec![0x55; 2],
+                    actual: vec![0; 2]
+                },
+                Mismatch {
+                    addr: 0x1200,
+                    expected: vec![0x55],
+                    actual: vec![0]
+                },
+            ]
+        );
+        assert_eq!(
+            mismatches[1].to_string(),
+            "0x001200..0x001201 (1 bytes): expected [55], read [00]"
+        );
+    }
+
+    #[test]
+    fn test_blank_check() {
+        let mut flash = MockFlash::new(FlashGeometry::default());
+        assert!(blank_check(
+            &mut flash,
+            0,
+            None,
+            &mut LogProgress::default(),
+            &CancelToken::new()
+        )
+        .unwrap()
+        .is_empty());
+        flash.data[0x1234] = 0;
+        flash.data[0xfffff] = 0x7f;
+        assert_eq!(
+            blank_check(
+                &mut flash,
+                0,
+                None,
+                &mut LogProgress::default(),
+                &CancelToken::new()
+            )
+            .unwrap(),
+            vec![0x1200, 0xfff00]
+        );
+        assert!(blank_check(
+            &mut flash,
+            0x1300,
+            Some(0x100),
+            &mut LogProgress::default(),
+            &CancelToken::new()
+        )
+        .unwrap()
+        .is_empty());
+        assert!(blank_check(
+            &mut flash,
+            0x1300,
+            Some(0x10_0000),
+            &mut LogProgress::default(),
+            &CancelToken::new()
+        )
+        .is_err());
+        assert!(blank_check(
+            &mut flash,
+            0x10_0000,
+            None,
+            &mut LogProgress::default(),
+            &CancelToken::new()
+        )
+        .unwrap()
+        .is_empty());
+    }
+
+    #[test]
+    fn test_read_bitstream() {
+        let mut flash = MockFlash::new(FlashGeometry::default());
+        let mut applet = Multiboot::new(0x100);
+        applet.add_image(vec![0; 0x10]).unwrap();
+        let data = applet.build(0x1080).unwrap();
+        flash.data[0x1080..0x1080 + data.len()].copy_from_slice(&data);
+        let bitstream = read_bitstream(&mut flash, 0x1080).unwrap();
+        assert!(bitstream.reboot);
+        assert_eq!(bitstream.len, 17);
+        assert!(read_bitstream(&mut flash, 0x2000).is_err());
+    }
+
+    #[test]
+    fn test_read_bitstream_blank() {
+        let mut flash = MockFlash::new(FlashGeometry::default());
+        let err = read_bitstream(&mut flash, 0).unwrap_err();
+        assert_eq!(err.to_string(), "Bitstream Error No iCE40 preamble at 0x0");
+    }
+
+    #[test]
+    fn test_digest() {
+        let mut flash = MockFlash::new(FlashGeometry::default());
+        flash.data.fill(0x55);
+        let image: Vec<u8> = (0..0x1234).map(|i: u32| i.to_le_bytes()[0]).collect();
+        let mut hasher = Hasher::default();
+        hasher.update(&image);
+        let expected = hasher.finish();
+
+        let mut programmer = FPGAProg::from_bytes(image.clone(), 0x10010);
+        programmer.preserve(&mut flash).unwrap();
+        programmer.erase(&mut flash).unwrap();
+        assert_eq!(programmer.digest(), None);
+        programmer.program(&mut flash).unwrap();
+        assert_eq!(programmer.digest(), Some(expected));
+
+        let mut dumper = FPGADump::new(vec![], 0x10010, image.len(), DumpFormat::Hexdump);
+        dumper.dump(&mut flash).unwrap();
+        assert_eq!(dumper.digest(), Some(expected));
+    }
+
+    #[test]
+    fn test_last_block() {
+        let geometry = FlashGeometry {
+            capacity: 0x100_0000,
+            ..FlashGeometry::default()
+        };
+        let mut flash = MockFlash::new(geometry);
+        flash.data.fill(0);
+        let mut programmer = FPGAProg::from_bytes(vec![1; 0x100], 0xff_ff00);
+        programmer.erase(&mut flash).unwrap();
+        assert!(flash.data[0xff_0000..].iter().all(|&b| b == 0xff));
+        assert_eq!(flash.data[0xfe_ffff], 0);
+        let mut progress = LogProgress::default();
+        erase_range(
+            &mut flash,
+            0,
+            geometry.capacity,
+            &mut progress,
+            &CancelToken::new(),
+        )
+        .unwrap();
+        assert!(flash.data.iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn test_erase_range() {
+        let mut flash = MockFlash::new(FlashGeometry::default());
+        flash.data.fill(0);
+        let mut reports = vec![];
+        let mut progress = |report: &ProgressReport| reports.push(report.done);
+        erase_range(&mut flash, 0x1_ffff, 2, &mut progress, &CancelToken::new()).unwrap();
+        assert_eq!(reports, vec![0, 0x1_0000, 0x2_0000]);
+        assert!(flash.data[..0x1_0000].iter().all(|&b| b == 0));
+        assert!(flash.data[0x1_0000..0x3_0000].iter().all(|&b| b == 0xff));
+        assert!(flash.data[0x3_0000..].iter().all(|&b| b == 0));
+        assert!(erase_range(
+            &mut flash,
+            0xf_ffff,
+            2,
+            &mut LogProgress::default(),
+            &CancelToken::new()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_progress() {
+        let mut flash = MockFlash::new(FlashGeometry::default());
+        let reports = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let mut programmer = FPGAProg::from_bytes(vec![0; 0x1_0100], 0x100);
+        let sink = reports.clone();
+        programmer.set_progress(Box::new(move |report: &ProgressReport| {
+            sink.lock()
+                .unwrap()
+                .push((report.phase, report.done, report.total));
+        }));
+        programmer.erase(&mut flash).unwrap();
+        programmer.program(&mut flash).unwrap();
+        let reports = reports.lock().unwrap();
+        let erase: Vec<_> = reports.iter().filter(|r| r.0 == Phase::Erase).collect();
+        assert_eq!(
+            erase,
+            [
+                &(Phase::Erase, 0, 0x2_0000),
+                &(Phase::Erase, 0x1_0000, 0x2_0000),
+                &(Phase::Erase, 0x2_0000, 0x2_0000)
+            ]
+        );
+        let program: Vec<_> = reports.iter().filter(|r| r.0 == Phase::Program).collect();
+        assert_eq!(program.len(), 0x102);
+        assert_eq!(program[1], &(Phase::Program, 0x100, 0x1_0100));
+        assert_eq!(
+            program.last().unwrap(),
+            &&(Phase::Program, 0x1_0100, 0x1_0100)
+        );
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut flash = MockFlash::new(FlashGeometry::default());
+        let cancel = CancelToken::new();
+        let mut programmer = FPGAProg::from_bytes(vec![0; 0x1000], 0);
+        programmer.set_cancel(cancel.clone());
+        let mut pages = 0;
+        programmer.set_progress(Box::new(move |report: &ProgressReport| {
+            pages += 1;
+            if pages == 3 {
+                cancel.cancel();
+            }
+            assert!(report.done <= 0x200);
+        }));
+        let err = programmer.program(&mut flash).unwrap_err();
+        assert!(matches!(err, Error::Cancelled(_)));
+        assert_eq!(err.to_string(), "Cancelled program after 512 of 4096 bytes");
+        assert_eq!(flash.data[..0x200], [0; 0x200]);
+        assert_eq!(flash.data[0x200], 0xff);
+    }
+}
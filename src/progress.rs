@@ -0,0 +1,183 @@
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+use crate::cancel::CancelToken;
+use crate::err::Error;
+
+const REPORT_PERIOD: Duration = Duration::from_secs(1);
+
+/// A long running operation on the flash.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Erase,
+    Program,
+    Verify,
+    Compare,
+    BlankCheck,
+    Dump,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Erase => "erase",
+            Self::Program => "program",
+            Self::Verify => "verify",
+            Self::Compare => "compare",
+            Self::BlankCheck => "blank_check",
+            Self::Dump => "dump",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How far an operation has got.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ProgressReport {
+    pub phase: Phase,
+    /// Bytes of flash done so far
+    pub done: usize,
+    /// Bytes of flash in the operation
+    pub total: usize,
+    /// Time since the operation started
+    pub elapsed: Duration,
+}
+
+impl ProgressReport {
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.done >= self.total
+    }
+
+    /// Whole percentage done, which is 100 for an empty operation.
+    #[must_use]
+    pub fn percent(&self) -> usize {
+        (100 * self.done).checked_div(self.total).unwrap_or(100)
+    }
+
+    /// Bytes per second since the operation started.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.done as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Receives progress reports from [`crate::FPGAProg`] and [`crate::FPGADump`],
+/// after each page or erase block and when an operation finishes.
+/// Closures taking a [`ProgressReport`] implement this.
+pub trait Progress {
+    fn report(&mut self, report: &ProgressReport);
+}
+
+impl<F: FnMut(&ProgressReport)> Progress for F {
+    fn report(&mut self, report: &ProgressReport) {
+        self(report);
+    }
+}
+
+/// Logs progress once per second, and when the operation finishes.
+#[derive(Debug, Default)]
+pub struct LogProgress {
+    last_report: Option<Instant>,
+}
+
+impl Progress for LogProgress {
+    fn report(&mut self, report: &ProgressReport) {
+        let now = Instant::now();
+        let last = *self.last_report.get_or_insert(now);
+        if now.duration_since(last) >= REPORT_PERIOD || report.is_finished() {
+            let progress = format!("{}%", report.percent());
+            info!(
+                %report.phase,
+                progress,
+                report.done,
+                report.total,
+                bytes_per_second = report.throughput().round(),
+            );
+            self.last_report = if report.is_finished() {
+                None
+            } else {
+                Some(now)
+            };
+        }
+    }
+}
+
+/// Reports one phase of an operation, timed from when it is created,
+/// and stops it when cancelled.
+pub(crate) struct Tracker {
+    phase: Phase,
+    total: usize,
+    start: Instant,
+    cancel: CancelToken,
+}
+
+impl Tracker {
+    pub(crate) fn new(phase: Phase, total: usize, cancel: &CancelToken) -> Self {
+        Self {
+            phase,
+            total,
+            start: Instant::now(),
+            cancel: cancel.clone(),
+        }
+    }
+
+    /// Report progress before the next page or block, unless cancelled.
+    pub(crate) fn step(
+        &self,
+        progress: &mut (impl Progress + ?Sized),
+        done: usize,
+    ) -> Result<(), Error> {
+        self.report(progress, done);
+        if self.cancel.is_cancelled() {
+            return Err(Error::Cancelled(format!(
+                "{} after {done} of {} bytes",
+                self.phase, self.total
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn report(&self, progress: &mut (impl Progress + ?Sized), done: usize) {
+        progress.report(&ProgressReport {
+            phase: self.phase,
+            done,
+            total: self.total,
+            elapsed: self.start.elapsed(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let report = ProgressReport {
+            phase: Phase::Program,
+            done: 0x8000,
+            total: 0x1_0000,
+            elapsed: Duration::from_millis(500),
+        };
+        assert_eq!(report.percent(), 50);
+        assert!(!report.is_finished());
+        assert!((report.throughput() - 65536.0).abs() < f64::EPSILON);
+        let empty = ProgressReport {
+            total: 0,
+            done: 0,
+            ..report
+        };
+        assert_eq!(empty.percent(), 100);
+        assert!(empty.is_finished());
+        assert_eq!(Phase::BlankCheck.to_string(), "blank_check");
+    }
+}
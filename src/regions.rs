@@ -0,0 +1,295 @@
+use std::io::Cursor;
+
+use tracing::info;
+
+use crate::err::Error;
+use crate::flash::FlashGeometry;
+use crate::programmer::FPGAProg;
+
+/// Data to be programmed at a flash address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub offset: usize,
+    pub data: Vec<u8>,
+}
+
+impl Region {
+    fn end(&self) -> Result<usize, Error> {
+        self.offset.checked_add(self.data.len()).ok_or_else(|| {
+            Error::Range(format!(
+                "{} bytes at {:#x} overflow the address space",
+                self.data.len(),
+                self.offset
+            ))
+        })
+    }
+}
+
+/// Non-overlapping regions which are programmed together.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Regions(Vec<Region>);
+
+impl Regions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a region. Empty regions are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the region overlaps one already added.
+    pub fn add(&mut self, offset: usize, data: Vec<u8>) -> Result<(), Error> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let region = Region { offset, data };
+        let end = region.end()?;
+        let index = self.0.partition_point(|other| other.offset < offset);
+        let before = index.checked_sub(1).and_then(|i| self.0.get(i));
+        for other in before.into_iter().chain(self.0.get(index)) {
+            if other.offset < end && region.offset < other.end()? {
+                return Err(Error::Range(format!(
+                    "{} bytes at {:#x} overlaps {} bytes at {:#x}",
+                    region.data.len(),
+                    region.offset,
+                    other.data.len(),
+                    other.offset
+                )));
+            }
+        }
+        self.0.insert(index, region);
+        Ok(())
+    }
+
+    /// Regions in address order.
+    #[must_use]
+    pub fn regions(&self) -> &[Region] {
+        &self.0
+    }
+
+    /// Move the regions, so that address `from` maps to `to`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if any region is below `from`.
+    pub fn relocate(&self, from: usize, to: usize) -> Result<Self, Error> {
+        let mut regions = Self::new();
+        for region in &self.0 {
+            let offset = region.offset.checked_sub(from).ok_or_else(|| {
+                Error::Range(format!("{:#x} is below base {from:#x}", region.offset))
+            })?;
+            let to = to.checked_add(offset).ok_or_else(|| {
+                Error::Range(format!("{to:#x} + {offset:#x} overflows the address space"))
+            })?;
+            regions.add(to, region.data.clone())?;
+        }
+        Ok(regions)
+    }
+
+    /// Regions which share an erase block are merged, with the gap between
+    /// them filled with `0xff`, so that each programmer erases distinct blocks.
+    /// Gaps are not compared with the flash, and `preserve` keeps their contents.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if any region does not fit in the flash.
+    pub fn programmers(
+        &self,
+        geometry: &FlashGeometry,
+    ) -> Result<Vec<FPGAProg<Cursor<Vec<u8>>>>, Error> {
+        let mut merged: Vec<(Region, Vec<(usize, usize)>)> = vec![];
+        for region in &self.0 {
+            if region.end()? > geometry.capacity {
+                return Err(Error::Range(format!(
+                    "{} bytes at {:#x} overruns {} byte flash",
+                    region.data.len(),
+                    region.offset,
+                    geometry.capacity
+                )));
+            }
+            match merged.last_mut() {
+                Some((last, gaps))
+                    if (last.end()? - 1) / geometry.block_size
+                        == region.offset / geometry.block_size =>
+                {
+                    let last_end = last.end()?;
+                    if region.offset > last_end {
+                        gaps.push((last_end, region.offset - last_end));
+                    }
+                    last.data.resize(region.offset - last.offset, 0xff);
+                    last.data.extend(&region.data);
+                }
+                _ => merged.push((region.clone(), vec![])),
+            }
+        }
+        Ok(merged
+            .into_iter()
+            .map(|(region, gaps)| {
+                info!(offset = region.offset, len = region.data.len(), "Region");
+                let mut programmer = FPGAProg::from_bytes(region.data, region.offset);
+                for (start, len) in gaps {
+                    programmer.add_gap(start, len);
+                }
+                programmer
+            })
+            .collect())
+    }
+}
+
+/// Collects data records, such as lines of a hex file, into contiguous regions.
+#[derive(Debug, Default)]
+pub(crate) struct RegionBuilder {
+    regions: Regions,
+    current: Option<Region>,
+}
+
+impl RegionBuilder {
+    pub(crate) fn push(&mut self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        match &mut self.current {
+            Some(current) if current.end()? == offset => current.data.extend(data),
+            _ => {
+                if let Some(region) = self.current.take() {
+                    self.regions.add(region.offset, region.data)?;
+                }
+                self.current = Some(Region {
+                    offset,
+                    data: data.to_vec(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<Regions, Error> {
+        if let Some(region) = self.current.take() {
+            self.regions.add(region.offset, region.data)?;
+        }
+        Ok(self.regions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_mocks::MockFlash;
+
+    #[test]
+    fn test_overlap() {
+        let mut regions = Regions::new();
+        regions.add(0x100, vec![1; 0x100]).unwrap();
+        regions.add(0x300, vec![2; 0x100]).unwrap();
+        regions.add(0x200, vec![3; 0x100]).unwrap();
+        regions.add(0x250, vec![]).unwrap();
+        assert!(regions.add(0x1ff, vec![4; 1]).is_err());
+        assert!(regions.add(0x3ff, vec![4; 1]).is_err());
+        assert!(regions.add(0, vec![4; 0x1000]).is_err());
+        assert!(regions.add(usize::MAX - 1, vec![4; 4]).is_err());
+        let offsets = regions
+            .regions()
+            .iter()
+            .map(|r| r.offset)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, vec![0x100, 0x200, 0x300]);
+    }
+
+    #[test]
+    fn test_relocate() {
+        let mut regions = Regions::new();
+        regions.add(0x2000_0100, vec![1]).unwrap();
+        let relocated = regions.relocate(0x2000_0000, 0x1000).unwrap();
+        assert_eq!(relocated.regions()[0].offset, 0x1100);
+        assert!(regions.relocate(0x2000_0101, 0).is_err());
+        assert!(regions.relocate(0, usize::MAX - 0x100).is_err());
+    }
+
+    #[test]
+    fn test_programmers() {
+        let geometry = FlashGeometry::default();
+        let mut regions = Regions::new();
+        regions.add(0, vec![1; 0x100]).unwrap();
+        regions.add(0x8000, vec![2; 0x100]).unwrap();
+        regions.add(0x30000, vec![3; 0x100]).unwrap();
+        let mut programmers = regions.programmers(&geometry).unwrap();
+        assert_eq!(programmers.len(), 2);
+
+        let mut flash = MockFlash::new(geometry);
+        flash.data.fill(0);
+        for programmer in &mut programmers {
+            programmer.erase(&mut flash).unwrap();
+        }
+        for programmer in &mut programmers {
+            programmer.program(&mut flash).unwrap();
+            programmer.verify(&mut flash).unwrap();
+        }
+        assert_eq!(flash.data[..0x100], [1; 0x100]);
+        assert_eq!(flash.data[0x100], 0xff);
+        assert_eq!(flash.data[0x8000..0x8100], [2; 0x100]);
+        assert_eq!(flash.data[0x20000], 0);
+        assert_eq!(flash.data[0x30000..0x30100], [3; 0x100]);
+
+        regions.add(geometry.capacity - 1, vec![4; 2]).unwrap();
+        assert!(regions.programmers(&geometry).is_err());
+    }
+
+    #[test]
+    fn test_compare_gaps() {
+        let geometry = FlashGeometry::default();
+        let mut regions = Regions::new();
+        regions.add(0x100, vec![1; 0x10]).unwrap();
+        regions.add(0x180, vec![2; 0x10]).unwrap();
+        let mut flash = MockFlash::new(geometry);
+        flash.data[0x100..0x110].fill(1);
+        flash.data[0x110..0x180].fill(0);
+        flash.data[0x180..0x190].fill(2);
+        let mut programmers = regions.programmers(&geometry).unwrap();
+        assert_eq!(programmers.len(), 1);
+        assert!(programmers[0].compare(&mut flash).unwrap().is_empty());
+        flash.data[0x18f] = 0;
+        let mismatches = programmers[0].compare(&mut flash).unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].addr, 0x18f);
+    }
+
+    #[test]
+    fn test_preserve_gaps() {
+        let geometry = FlashGeometry::default();
+        let mut regions = Regions::new();
+        regions.add(0x100, vec![1; 0x10]).unwrap();
+        regions.add(0x380, vec![2; 0x10]).unwrap();
+        let mut flash = MockFlash::new(geometry);
+        flash.data[..0x1000].fill(0x33);
+        let mut programmers = regions.programmers(&geometry).unwrap();
+        let programmer = &mut programmers[0];
+        programmer.preserve(&mut flash).unwrap();
+        programmer.erase(&mut flash).unwrap();
+        programmer.program(&mut flash).unwrap();
+        programmer.verify(&mut flash).unwrap();
+        assert_eq!(flash.data[..0x100], [0x33; 0x100]);
+        assert_eq!(flash.data[0x100..0x110], [1; 0x10]);
+        assert_eq!(flash.data[0x110..0x380], [0x33; 0x270]);
+        assert_eq!(flash.data[0x380..0x390], [2; 0x10]);
+        assert_eq!(flash.data[0x390..0x1000], [0x33; 0xc70]);
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut builder = RegionBuilder::default();
+        builder.push(0x10, &[1, 2]).unwrap();
+        builder.push(0x12, &[3]).unwrap();
+        builder.push(0x20, &[4]).unwrap();
+        builder.push(0x21, &[5]).unwrap();
+        builder.push(0x0, &[6]).unwrap();
+        let regions = builder.finish().unwrap();
+        let regions = regions
+            .regions()
+            .iter()
+            .map(|r| (r.offset, r.data.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            regions,
+            vec![(0, vec![6]), (0x10, vec![1, 2, 3]), (0x20, vec![4, 5])]
+        );
+    }
+}
@@ -1,3 +1,7 @@
 use std::io::{Read, Write};
 
+/// A transport to the iceFUN firmware, such as a serial port.
+/// Anything which implements [`Read`] and [`Write`] can be used.
 pub trait SerialPort: Read + Write {}
+
+impl<T: Read + Write + ?Sized> SerialPort for T {}
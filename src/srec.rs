@@ -0,0 +1,92 @@
+use tracing::instrument;
+
+use crate::err::Error;
+use crate::image::decode_hex;
+use crate::regions::{RegionBuilder, Regions};
+
+/// Parse Motorola S-records into regions.
+///
+/// # Errors
+///
+/// Will return `Err` if a record is malformed, or records overlap.
+#[instrument(skip(text))]
+pub fn parse(text: &str) -> Result<Regions, Error> {
+    let mut builder = RegionBuilder::default();
+    for (line_no, line) in text.lines().enumerate() {
+        let err = |msg: &str| Error::Parse(format!("line {}: {msg}", line_no + 1));
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (record_type, record) = line
+            .strip_prefix('S')
+            .filter(|rest| rest.is_char_boundary(1))
+            .map(|rest| rest.split_at(1))
+            .and_then(|(record_type, hex)| Some((record_type, decode_hex(hex)?)))
+            .ok_or_else(|| err("invalid record"))?;
+        if record.is_empty() || record.len() != 1 + usize::from(record[0]) {
+            return Err(err("invalid length"));
+        }
+        if record.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0xff {
+            return Err(err("invalid checksum"));
+        }
+        let addr_len = match record_type {
+            "0" | "1" | "5" | "9" => 2,
+            "2" | "6" | "8" => 3,
+            "3" | "7" => 4,
+            _ => return Err(err(&format!("unknown record type S{record_type}"))),
+        };
+        if record.len() < 2 + addr_len {
+            return Err(err("invalid length"));
+        }
+        let addr = record[1..=addr_len]
+            .iter()
+            .fold(0usize, |addr, &b| (addr << 8) | usize::from(b));
+        let data = &record[1 + addr_len..record.len() - 1];
+        match record_type {
+            "1" | "2" | "3" => builder.push(addr, data)?,
+            "7" | "8" | "9" => break,
+            _ => {}
+        }
+    }
+    builder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "\
+S00600004844521B
+S107000001020304EE
+S206010000AABB93
+S30800000004CCDDFF4B
+S5030003F9
+S9030000FC
+";
+        let regions = parse(text).unwrap();
+        let regions = regions
+            .regions()
+            .iter()
+            .map(|r| (r.offset, r.data.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            regions,
+            vec![
+                (0, vec![1, 2, 3, 4]),
+                (4, vec![0xcc, 0xdd, 0xff]),
+                (0x10000, vec![0xaa, 0xbb]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse("107000001020304EE").is_err());
+        assert!(parse("S107000001020304EF").is_err());
+        assert!(parse("S4030003F9").is_err());
+        assert!(parse("S1").is_err());
+    }
+}
@@ -6,8 +6,9 @@ use std::rc::Rc;
 
 use crate::{
     cmds::{CmdArgs, CmdReply, Command},
+    dev::{Dumpable, FlashDevice, Programmable},
     err::Error,
-    serialport::SerialPort,
+    flash::FlashGeometry,
 };
 
 pub(crate) struct ReadBuf(Cursor<Vec<u8>>);
@@ -37,15 +38,12 @@ pub(crate) struct MockPort {
 }
 
 impl MockPort {
-    fn new(data: Vec<u8>) -> Self {
+    pub(crate) fn new(data: Vec<u8>) -> Self {
         Self {
             reader: Rc::new(RefCell::new(ReadBuf(Cursor::new(data)))),
             writer: Rc::new(RefCell::new(WriteBuf(Cursor::new(vec![])))),
         }
     }
-    fn test_port(&self) -> Box<dyn SerialPort> {
-        Box::new(self.clone())
-    }
     pub(crate) fn written(self) -> Vec<u8> {
         Rc::into_inner(self.writer)
             .unwrap()
@@ -71,8 +69,6 @@ impl Write for MockPort {
     }
 }
 
-impl SerialPort for MockPort {}
-
 pub(crate) trait TestCmd<A, R> {
     type Error: Debug;
     fn test(&self, data: Vec<u8>, args: &A) -> (MockPort, Result<R, Self::Error>);
@@ -91,7 +87,71 @@ impl<A: CmdArgs, R: CmdReply> TestCmd<A, R> for Command<A, R> {
     type Error = Error;
     fn test(&self, data: Vec<u8>, args: &A) -> (MockPort, Result<R, Self::Error>) {
         let port = MockPort::new(data);
-        let result = self.run_args(&mut port.test_port(), args);
+        let result = self.run_args(&mut port.clone(), args);
         (port, result)
     }
 }
+
+/// In-memory flash behaving like the iceFUN firmware.
+pub(crate) struct MockFlash {
+    pub(crate) data: Vec<u8>,
+    geometry: FlashGeometry,
+}
+
+impl MockFlash {
+    pub(crate) fn new(geometry: FlashGeometry) -> Self {
+        Self {
+            data: vec![0xff; geometry.capacity],
+            geometry,
+        }
+    }
+
+    fn padded(&self, data: &[u8]) -> Vec<u8> {
+        let mut page = data.to_vec();
+        page.resize(self.geometry.page_size, 0);
+        page
+    }
+}
+
+impl FlashDevice for MockFlash {
+    fn geometry(&self) -> &FlashGeometry {
+        &self.geometry
+    }
+}
+
+impl Programmable for MockFlash {
+    fn erase_chip(&mut self) -> Result<(), Error> {
+        self.data.fill(0xff);
+        Ok(())
+    }
+
+    fn erase64k(&mut self, page: u8) -> Result<(), Error> {
+        let start = usize::from(page) * self.geometry.block_size;
+        self.data[start..start + self.geometry.block_size].fill(0xff);
+        Ok(())
+    }
+
+    fn program_page(&mut self, addr: usize, data: &[u8]) -> Result<(), Error> {
+        let page = self.padded(data);
+        for (flash, byte) in self.data[addr..addr + page.len()].iter_mut().zip(page) {
+            *flash &= byte;
+        }
+        Ok(())
+    }
+
+    fn verify_page(&mut self, addr: usize, data: &[u8]) -> Result<(), Error> {
+        let page = self.padded(data);
+        if self.data[addr..addr + page.len()] == page {
+            Ok(())
+        } else {
+            Err(Error::Cmd(format!("verify failed at {addr:#x}")))
+        }
+    }
+}
+
+impl Dumpable for MockFlash {
+    fn read_page(&mut self, addr: usize, len: usize, output: &mut impl Write) -> Result<(), Error> {
+        output.write_all(&self.data[addr..addr + len])?;
+        Ok(())
+    }
+}
@@ -4,10 +4,16 @@ use std::{
 };
 
 use anyhow::Result;
-use serialport::{FlowControl, SerialPort, SerialPortBuilder, SerialPortType};
-use tracing::trace;
+use serde::Serialize;
+use serialport::{FlowControl, SerialPort, SerialPortType};
+use tracing::{info, trace, warn};
 use tracing_subscriber::filter::LevelFilter;
 
+/// USB vendor ID of the iceFUN board
+const ICEFUN_VID: u16 = 0x04d8;
+/// USB product ID of the iceFUN board
+const ICEFUN_PID: u16 = 0xffee;
+
 struct AddrSuffix {
     suffix: char,
     multiplier: usize,
@@ -55,51 +61,187 @@ impl<Port: SerialPort> Write for TracePort<Port> {
     }
 }
 
-impl<Port: SerialPort> crate::serialport::SerialPort for TracePort<Port> {}
+/// An attached iceFUN board, as reported by USB.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BoardInfo {
+    pub port: String,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+/// Attached iceFUN boards, in the order they are enumerated.
+pub fn find_boards() -> Result<Vec<BoardInfo>> {
+    Ok(serialport::available_ports()?
+        .into_iter()
+        .filter_map(|port_info| match port_info.port_type {
+            SerialPortType::UsbPort(usb_port_info)
+                if usb_port_info.vid == ICEFUN_VID && usb_port_info.pid == ICEFUN_PID =>
+            {
+                Some(BoardInfo {
+                    port: port_info.port_name,
+                    serial_number: usb_port_info.serial_number,
+                    manufacturer: usb_port_info.manufacturer,
+                    product: usb_port_info.product,
+                })
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+/// Choose a board by USB serial number and by `index` among the boards which match.
+/// With neither, the first board is used, as before the selectors were added.
+fn select_board(
+    boards: Vec<BoardInfo>,
+    serial: Option<&str>,
+    index: Option<usize>,
+) -> Result<BoardInfo> {
+    let found = boards.len();
+    let mut matching: Vec<BoardInfo> = boards
+        .into_iter()
+        .filter(|board| serial.is_none() || board.serial_number.as_deref() == serial)
+        .collect();
+    let described = serial.map_or_else(
+        || "iceFUN boards".to_string(),
+        |serial| format!("boards with serial number {serial}"),
+    );
+    if let Some(index) = index {
+        if index >= matching.len() {
+            anyhow::bail!("No board {index}, found {} {described}", matching.len());
+        }
+        return Ok(matching.swap_remove(index));
+    }
+    match matching.len() {
+        0 if found > 0 => anyhow::bail!("No board with serial number {}", serial.unwrap_or("")),
+        0 => anyhow::bail!("No port"),
+        1 => Ok(matching.remove(0)),
+        n if serial.is_none() => {
+            warn!(
+                port = matching[0].port,
+                "Found {n} {described}, using the first, use --serial or --index to choose one"
+            );
+            Ok(matching.remove(0))
+        }
+        n => anyhow::bail!("Found {n} {described}, use --index or --port to choose one"),
+    }
+}
+
+/// Open the serial port of an iceFUN board.
+pub fn open_board(port: &str) -> Result<impl crate::serialport::SerialPort> {
+    let mut port = serialport::new(port, 9600).open_native()?;
+    port.set_flow_control(FlowControl::None)?;
+    port.set_timeout(Duration::from_secs(10))?;
+    Ok(TracePort(port))
+}
 
+/// Logging options.
 #[derive(clap::Args, Debug)]
-pub struct CommonArgs {
+pub struct LogArgs {
+    /// Logging level. `Off` for silent operation.
+    #[arg(short, long, default_value = "Info")]
+    pub log_level: LevelFilter,
+
+    /// Write log events to stdout as JSON lines, for automation
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Options choosing the board, for commands which use one.
+#[derive(clap::Args, Debug)]
+pub struct BoardArgs {
     /// Use the specified USB device
     #[arg(short, long)]
     pub port: Option<String>,
 
-    /// Logging level. `Off` for silent operation.
-    #[arg(short, long, default_value = "Info")]
-    pub log_level: LevelFilter,
+    /// Use the board with this USB serial number
+    #[arg(long, conflicts_with = "port")]
+    pub serial: Option<String>,
+
+    /// Use the board at this index in the `list` output, counting from 0.
+    /// With --serial, counts only boards with that serial number.
+    #[arg(long, conflicts_with = "port")]
+    pub index: Option<usize>,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+/// Options for commands which access the flash.
+#[derive(clap::Args, Debug)]
+pub struct CommonArgs {
+    #[command(flatten)]
+    pub board: BoardArgs,
 
     /// EEPROM start offset
     #[arg(short, long, default_value = "0", value_parser = parse_addr)]
     pub offset: usize,
 }
 
-impl CommonArgs {
+impl LogArgs {
     pub fn init_logger(&self) {
-        let subscriber = tracing_subscriber::FmtSubscriber::builder()
-            .with_max_level(self.log_level)
-            .finish();
-        tracing::subscriber::set_global_default(subscriber)
-            .expect("setting tracing default failed");
+        let builder = tracing_subscriber::FmtSubscriber::builder().with_max_level(self.log_level);
+        let result = if self.json {
+            let subscriber = builder
+                .json()
+                .flatten_event(true)
+                .with_writer(std::io::stdout)
+                .finish();
+            tracing::subscriber::set_global_default(subscriber)
+        } else {
+            let subscriber = builder.with_writer(std::io::stderr).finish();
+            tracing::subscriber::set_global_default(subscriber)
+        };
+        result.expect("setting tracing default failed");
     }
+}
 
-    fn find_port(&self) -> Result<SerialPortBuilder> {
+impl BoardArgs {
+    fn find_port(&self) -> Result<String> {
         if let Some(port) = &self.port {
-            Ok(serialport::new(port, 9600))
+            Ok(port.clone())
         } else {
-            for port_info in serialport::available_ports()? {
-                if let SerialPortType::UsbPort(usb_port_info) = port_info.port_type {
-                    if usb_port_info.vid == 0x04d8 && usb_port_info.pid == 0xffee {
-                        return Ok(serialport::new(port_info.port_name, 9600));
-                    }
-                }
-            }
-            anyhow::bail!("No port")
+            let board = select_board(find_boards()?, self.serial.as_deref(), self.index)?;
+            info!(port = board.port, serial_number = ?board.serial_number, "Found board");
+            Ok(board.port)
+        }
+    }
+
+    pub fn open_port(&self) -> Result<impl crate::serialport::SerialPort> {
+        open_board(&self.find_port()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(port: &str, serial_number: &str) -> BoardInfo {
+        BoardInfo {
+            port: port.to_string(),
+            serial_number: Some(serial_number.to_string()),
+            manufacturer: None,
+            product: None,
         }
     }
 
-    pub fn open_port(&self) -> Result<Box<dyn crate::serialport::SerialPort>> {
-        let mut port = self.find_port()?.open_native()?;
-        port.set_flow_control(FlowControl::None)?;
-        port.set_timeout(Duration::from_secs(10))?;
-        Ok(Box::new(TracePort(port)))
+    #[test]
+    fn test_select_board() {
+        let boards = vec![board("a", "1"), board("b", "2"), board("c", "2")];
+        let select = |serial, index| select_board(boards.clone(), serial, index).map(|b| b.port);
+        assert_eq!(select(Some("1"), None).unwrap(), "a");
+        assert_eq!(select(None, Some(1)).unwrap(), "b");
+        assert_eq!(select(Some("2"), Some(1)).unwrap(), "c");
+        assert!(select(Some("2"), None).is_err());
+        assert!(select(Some("3"), None).is_err());
+        assert_eq!(select(None, None).unwrap(), "a");
+        assert!(select(None, Some(3)).is_err());
+        assert_eq!(
+            select_board(vec![board("a", "1")], None, None)
+                .unwrap()
+                .port,
+            "a"
+        );
+        assert!(select_board(vec![], None, None).is_err());
     }
 }